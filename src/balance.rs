@@ -4,8 +4,9 @@ use std::fmt;
 
 use crypto::PublicKey;
 use block::HashedBlockExt;
-use error::{IroncError, IroncResult};
+use error::{ErrorKind, IroncError, IroncResult};
 use ironcoin_pb::{Balance, BalancePatch, HashedBlock, Transaction, Transfer};
+use tx::TransactionExt;
 
 pub trait BalancePatchExt {
     fn decode_public_key(&self) -> IroncResult<PublicKey>;
@@ -35,6 +36,29 @@ pub trait LedgerWriter {
                    -> IroncResult<()>;
 }
 
+// An in memory ledger, where addresses never seen have a zero balance.
+impl LedgerReader for HashMap<PublicKey, Balance> {
+    fn get_balance(&self, address: &PublicKey) -> IroncResult<Balance> {
+        match self.get(address) {
+            Some(balance) => Ok(balance.clone()),
+            None => {
+                let mut balance = Balance::new();
+                balance.set_tokens(0);
+                balance.set_op_index(0);
+                Ok(balance)
+            }
+        }
+    }
+}
+
+impl LedgerWriter for HashMap<PublicKey, Balance> {
+    fn set_balance(&mut self, address: &PublicKey, balance: Balance)
+                   -> IroncResult<()> {
+        self.insert(address.clone(), balance);
+        Ok(())
+    }
+}
+
 pub trait Patchable {
     fn apply_patch(&mut self, patch: BalancePatch) -> IroncResult<()>;
 }
@@ -83,9 +107,10 @@ impl<'a, L: 'a + LedgerReader> LedgerSnapshot<'a, L> {
         let destination_pk =
             try!(PublicKey::from_slice(transfer.get_destination_pk()));
         let mut source = try!(self.get_balance(&source_pk));
-        let mut destination = try!(self.get_balance(&destination_pk));
+        let debit = try!(transfer.get_tokens().checked_add(transfer.get_fee())
+                         .ok_or(IroncError::new("Transfer amount overflows.")));
 
-        if source.get_tokens() >= transfer.get_tokens() {
+        if source.get_tokens() >= debit {
             // println!("source: {}",
             //          transfer.get_source_pk().to_base64(base64::STANDARD));
             // println!("destination: {}",
@@ -96,41 +121,76 @@ impl<'a, L: 'a + LedgerReader> LedgerSnapshot<'a, L> {
                 //          source.get_tokens(),
                 //          source.get_tokens() - transfer.get_tokens());
 
-                let source_tokens = source.get_tokens() - transfer.get_tokens();
+                let source_tokens = source.get_tokens() - debit;
                 let source_op_index = source.get_op_index() + 1;
-                let dest_tokens = destination.get_tokens() + transfer.get_tokens();
                 source.set_tokens(source_tokens);
                 source.set_op_index(source_op_index);
-                destination.set_tokens(dest_tokens);
-
                 try!(self.set_balance(&source_pk, source));
+
+                // Read after the source is written, in case they are the
+                // same account.
+                let mut destination = try!(self.get_balance(&destination_pk));
+                let dest_tokens = destination.get_tokens() + transfer.get_tokens();
+                destination.set_tokens(dest_tokens);
                 try!(self.set_balance(&destination_pk, destination));
                 Ok(())
             } else {
-                Err(IroncError::new(&format!(
+                Err(IroncError::with_kind(ErrorKind::DoubleSpend, &format!(
                     "Wrong op number for source address {}: op_index was \
                      {} != {} (required)", source_pk, transfer.get_op_index(),
                     source.get_op_index())))
             }
         } else {
-            Err(IroncError::new(&format!(
+            Err(IroncError::with_kind(ErrorKind::DoubleSpend, &format!(
                 "Not enough funds. Source address balance is {} but \
                  {} tokens were transferred: {} -> {}", source.get_tokens(),
-                transfer.get_tokens(), source_pk, destination_pk)))
+                debit, source_pk, destination_pk)))
         }
     }
 
     pub fn apply_transaction(
         &mut self, transaction: &Transaction) -> IroncResult<()> {
+        if transaction.is_coinbase() {
+            return self.add_coinbase(transaction);
+        }
         for transfer in transaction.get_commit().get_transfers().iter() {
             try!(self.add_transfer(transfer));
         }
         Ok(())
     }
 
+    // Coinbases are only checked by their block, see
+    // HashedBlockExt::verify_coinbase.
+    fn add_coinbase(&mut self, coinbase: &Transaction) -> IroncResult<()> {
+        for transfer in coinbase.get_commit().get_transfers().iter() {
+            let destination_pk =
+                try!(PublicKey::from_slice(transfer.get_destination_pk()));
+            let mut destination = try!(self.get_balance(&destination_pk));
+            let dest_tokens = try!(
+                destination.get_tokens().checked_add(transfer.get_tokens())
+                    .ok_or(IroncError::new("Coinbase balance overflows.")));
+            destination.set_tokens(dest_tokens);
+            try!(self.set_balance(&destination_pk, destination));
+        }
+        Ok(())
+    }
+
+    // Without a coinbase, the fees go to the block's staker.
     pub fn apply_block(&mut self, block: &HashedBlock) -> IroncResult<()> {
+        let mut fees = 0u64;
         for transaction in block.get_block().get_transactions().iter() {
             try!(self.apply_transaction(transaction));
+            fees = try!(fees.checked_add(try!(transaction.total_fees())).ok_or(
+                IroncError::new("Block fees overflow.")));
+        }
+        if fees > 0 && block.coinbase().is_none() {
+            let staker_pk = try!(block.decode_staker_pk());
+            let mut staker = try!(self.get_balance(&staker_pk));
+            let staker_tokens = try!(staker.get_tokens().checked_add(fees)
+                .ok_or(IroncError::with_kind(ErrorKind::Conservation,
+                                             "Staker balance overflows.")));
+            staker.set_tokens(staker_tokens);
+            try!(self.set_balance(&staker_pk, staker));
         }
         Ok(())
     }
@@ -302,3 +362,48 @@ fn test_ledger_snapshot_make_patches() {
     snapshot.set_balance(&pk1, balance_pk1.clone());
     println!("{:?}", snapshot.make_patches());
 }
+
+#[test]
+fn test_ledger_snapshot_apply_block_fees() {
+    use std::u64;
+
+    use block::SignedBlockExt;
+    use crypto::gen_keypair;
+    use tx::TransactionBuilder;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (staker_pk, staker_sk) = gen_keypair();
+
+    let mut balance_pk1 = Balance::new();
+    balance_pk1.set_tokens(100);
+    balance_pk1.set_op_index(0);
+    let ledger_cell = RefCell::new(HashMap::new());
+    ledger_cell.borrow_mut().insert(pk1.clone(), balance_pk1);
+    let ledger = TestLedgerHashMap { ledger: ledger_cell };
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 90, 4, 0);
+    let mut block = HashedBlock::new();
+    block.mut_signed_block().mut_block().mut_transactions()
+        .push(tx_builder.build().unwrap());
+    block.mut_signed_block().sign(&staker_sk);
+
+    let mut snapshot = LedgerSnapshot::new(&ledger);
+    snapshot.apply_block(&block).unwrap();
+    assert_eq!(6, snapshot.get_balance(&pk1).unwrap().get_tokens());
+    assert_eq!(90, snapshot.get_balance(&pk2).unwrap().get_tokens());
+    assert_eq!(4, snapshot.get_balance(&staker_pk).unwrap().get_tokens());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 6, 1, 1);
+    assert!(snapshot.apply_transaction(&tx_builder.build().unwrap()).is_err());
+
+    // The fees must not overflow the staker's balance.
+    let mut balance_staker = Balance::new();
+    balance_staker.set_tokens(u64::MAX - 3);
+    ledger.ledger.borrow_mut().insert(staker_pk.clone(), balance_staker);
+    let mut snapshot = LedgerSnapshot::new(&ledger);
+    assert_eq!(ErrorKind::Conservation,
+               snapshot.apply_block(&block).unwrap_err().kind());
+}
@@ -1,14 +1,47 @@
-use time::now_utc;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
-use crypto::{HashDigest, PublicKey, SecretKey, Signature,
-             gen_keypair, hash_message, sign_message, verify_signed_message};
-use error::{IroncError, IroncResult};
-use ironcoin_pb::{Block, BlockWithDiff, HashedBlock, SignedBlock, Transaction};
-use tx::{TransactionBuilder, TransactionExt};
+use protobuf::{self, Message, RepeatedField};
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json::Json;
+use time::{Timespec, now_utc};
+
+use bloom::BloomFilter;
+use crypto::{DEFAULT_SIGNATURE_SCHEME, HASHBYTES, HashDigest, HashFunction,
+             PublicKey, SecretKey, Sha512, Signature, SignatureScheme, gen_keypair,
+             gen_keypair_from_seed, hash_message, hash_message_with,
+             verify_signature};
+use error::{ErrorKind, IroncError, IroncResult};
+use ironcoin_pb::{Block, Block_SignatureScheme, BlockHeader, BlockPatch,
+                  BlockWithDiff, HashedBlock, SignedBlock, Transaction};
+use json;
+use merkle::{self, has_duplicate_pair, merkle_root, transaction_leaves};
+use tx::{TransactionBuilder, TransactionExt, make_coinbase};
+
+// Where builders get the timestamp of the blocks they make, so tests can fix
+// it.
+pub trait Clock {
+    fn now(&self) -> Timespec;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timespec { now_utc().to_timespec() }
+}
 
 fn create_genesis_block(staker_pk: &PublicKey, staker_sk: &SecretKey,
                         tx: Transaction) -> IroncResult<HashedBlock> {
-    if tx.get_commit().get_bounty() != 0 || tx.get_commit().has_bounty_pk() {
+    create_genesis_block_at(staker_pk, staker_sk, tx, SystemClock.now().sec)
+}
+
+fn create_genesis_block_at(staker_pk: &PublicKey, staker_sk: &SecretKey,
+                           tx: Transaction, timestamp: i64)
+                           -> IroncResult<HashedBlock> {
+    if tx.has_bounty() {
         return Err(IroncError::new(
             "Transactions must not have a bounty set in a genesis block."));
     }
@@ -16,63 +49,410 @@ fn create_genesis_block(staker_pk: &PublicKey, staker_sk: &SecretKey,
     let mut genesis = HashedBlock::new();
     genesis.mut_signed_block().mut_block().set_staker_pk(staker_pk.0.to_vec());
     genesis.mut_signed_block().mut_block().set_previous(
-        HashDigest::from_u64(0).0.to_vec());
+        HashDigest::zero().0.to_vec());
     genesis.mut_signed_block().mut_block().set_height(0);
-    genesis.mut_signed_block().mut_block().set_timestamp(
-        now_utc().to_timespec().sec);
+    genesis.mut_signed_block().mut_block().set_timestamp(timestamp);
     genesis.mut_signed_block().mut_block().mut_transactions().push(tx);
 
-    genesis.mut_signed_block().sign(staker_sk);
-    genesis.compute_hash();
+    genesis.reseal(staker_sk);
     Ok(genesis)
 }
 
+fn block_fees<'a, I>(transactions: I) -> IroncResult<u64>
+    where I: Iterator<Item=&'a Transaction>
+{
+    let mut fees = 0u64;
+    for tx in transactions {
+        fees = try!(fees.checked_add(try!(tx.total_fees())).ok_or(
+            IroncError::with_kind(ErrorKind::Conservation,
+                                  "Block fees overflow.")));
+    }
+    Ok(fees)
+}
+
+// A block after the genesis may have at most one coinbase, which mints
+// exactly `block_reward` plus the fees of the block's other transactions.
+fn check_coinbase(block: &HashedBlock, block_reward: u64, required: bool)
+                  -> IroncResult<()> {
+    let transactions = block.get_block().get_transactions();
+    let mut coinbases = transactions.iter().filter(|tx| tx.is_coinbase());
+    let coinbase = coinbases.next();
+    if coinbases.next().is_some() {
+        return Err(IroncError::with_kind(ErrorKind::Conservation,
+            "Block has more than one coinbase transaction."));
+    }
+    let coinbase = match coinbase {
+        Some(coinbase) => coinbase,
+        None if required && block.get_height() > 0 => return Err(
+            IroncError::with_kind(ErrorKind::Conservation,
+                                  "Block has no coinbase transaction.")),
+        None => return Ok(())
+    };
+    if block.get_height() == 0 {
+        return Err(IroncError::with_kind(ErrorKind::Conservation,
+            "Genesis block must not have a coinbase transaction."));
+    }
+    let transfers = coinbase.get_commit().get_transfers();
+    if transfers.len() != 1 || coinbase.get_signatures().len() != 0 ||
+        coinbase.has_bounty()
+    {
+        return Err(IroncError::new(
+            "Coinbase must have a single transfer, no signatures or bounty."));
+    }
+    let transfer = &transfers[0];
+    if transfer.has_source_pk() || transfer.get_fee() != 0 ||
+        transfer.get_op_index() != block.get_height()
+    {
+        return Err(IroncError::new(
+            "Coinbase transfer must have no source or fee and the block \
+             height as op_index."));
+    }
+    try!(PublicKey::from_slice(transfer.get_destination_pk()));
+    let fees = try!(block_fees(
+        transactions.iter().filter(|tx| !tx.is_coinbase())));
+    let allowed = try!(fees.checked_add(block_reward).ok_or(
+        IroncError::with_kind(ErrorKind::Conservation,
+                              "Block reward overflows.")));
+    if transfer.get_tokens() != allowed {
+        return Err(IroncError::with_kind(ErrorKind::Conservation, &format!(
+            "Coinbase mints {} tokens != {} (reward plus fees).",
+            transfer.get_tokens(), allowed)));
+    }
+    Ok(())
+}
+
+const VERIFY_THREADS: usize = 4;
+
+fn verify_transactions_parallel(transactions: &[Transaction])
+                                -> IroncResult<()> {
+    // Protobuf messages can't be shared between threads, so the signatures
+    // are decoded up front and only the raw checks are farmed out.
+    let mut checks = vec![];
+    for tx in transactions.iter().filter(|tx| !tx.is_coinbase()) {
+        let commit_bytes = try!(tx.get_commit().write_to_bytes());
+        checks.push((commit_bytes, try!(tx.decode_signature_checks())));
+    }
+    if checks.len() == 0 {
+        return Ok(());
+    }
+    let chunk_size = max(1, (checks.len() + VERIFY_THREADS - 1) / VERIFY_THREADS);
+    let failed = AtomicBool::new(false);
+    let guards: Vec<_> = checks.chunks(chunk_size).map(|chunk| {
+        let failed = &failed;
+        thread::scoped(move || -> IroncResult<()> {
+            for &(ref commit_bytes, ref tx_checks) in chunk.iter() {
+                if failed.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                for &(ref public_key, ref signature) in tx_checks.iter() {
+                    let verified =
+                        verify_signature(public_key, commit_bytes, signature);
+                    if verified.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                        return verified;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }).collect();
+    let mut result = Ok(());
+    for guard in guards.into_iter() {
+        let chunk_result = guard.join();
+        if result.is_ok() { result = chunk_result; }
+    }
+    result
+}
+
+// A transfer without a source is made from the builder's own keypair.
+struct GenesisTransfer {
+    source: Option<(SecretKey, PublicKey, u32)>,
+    destination: PublicKey,
+    tokens: u64
+}
+
 pub struct GenesisBuilder {
-    transfers: Vec<(PublicKey, u64)>
+    public_key: PublicKey,
+    secret_key: SecretKey,
+    transfers: Vec<GenesisTransfer>
 }
 
 impl GenesisBuilder {
     pub fn new() -> GenesisBuilder {
+        let (public_key, secret_key) = gen_keypair();
+        GenesisBuilder::with_keypair(public_key, secret_key)
+    }
+
+    pub fn with_keypair(public_key: PublicKey, secret_key: SecretKey)
+                        -> GenesisBuilder {
         GenesisBuilder {
+            public_key: public_key,
+            secret_key: secret_key,
             transfers: vec![]
         }
     }
 
-    pub fn add_transfer(&mut self, destination: PublicKey, tokens: u64) {
-        self.transfers.push((destination, tokens));
+    pub fn public_key(&self) -> &PublicKey { &self.public_key }
+
+    pub fn secret_key(&self) -> &SecretKey { &self.secret_key }
+
+    pub fn add_transfer(&mut self, destination: PublicKey, tokens: u64)
+                        -> &mut Self {
+        self.transfers.push(GenesisTransfer {
+            source: None,
+            destination: destination,
+            tokens: tokens
+        });
+        self
     }
 
-    pub fn build(self) -> HashedBlock {
-        let (public_key, secret_key) = gen_keypair();
+    // For minting from other keys than the builder's, e.g. one per founder.
+    // As with any transaction, each source's op_nums must be consecutive.
+    pub fn add_transfer_from(&mut self, source_sk: SecretKey,
+                             source_pk: PublicKey, destination: PublicKey,
+                             tokens: u64, op_num: u32) -> &mut Self {
+        self.transfers.push(GenesisTransfer {
+            source: Some((source_sk, source_pk, op_num)),
+            destination: destination,
+            tokens: tokens
+        });
+        self
+    }
+
+    pub fn build(self) -> IroncResult<HashedBlock> {
+        self.build_with_clock(&SystemClock)
+    }
+
+    pub fn build_with_clock<C: Clock>(self, clock: &C)
+                                      -> IroncResult<HashedBlock> {
+        self.build_at(clock.now().sec)
+    }
+
+    pub fn build_deterministic(self, seed: &[u8], timestamp: i64)
+                               -> IroncResult<HashedBlock> {
+        let (public_key, secret_key) = gen_keypair_from_seed(seed);
+        GenesisBuilder {
+            public_key: public_key,
+            secret_key: secret_key,
+            transfers: self.transfers
+        }.build_at(timestamp)
+    }
+
+    fn build_at(self, timestamp: i64) -> IroncResult<HashedBlock> {
+        let mut total_supply = 0u64;
+        for transfer in self.transfers.iter() {
+            if transfer.tokens == 0 {
+                return Err(IroncError::new(&format!(
+                    "Genesis transfer of 0 tokens to {}.",
+                    transfer.destination)));
+            }
+            total_supply = try!(total_supply.checked_add(transfer.tokens)
+                                .ok_or(IroncError::new(
+                                    "Genesis total supply overflows.")));
+        }
         let mut tx_builder = TransactionBuilder::new();
         let mut op_num = 0u32;
-        for (destination, tokens) in self.transfers.into_iter() {
-            tx_builder.add_transfer(
-                &secret_key, &public_key, &destination, tokens, op_num);
-            op_num += 1;
+        for transfer in self.transfers.iter() {
+            match transfer.source {
+                Some((ref source_sk, ref source_pk, source_op_num)) => {
+                    tx_builder.add_transfer(
+                        source_sk, source_pk, &transfer.destination,
+                        transfer.tokens, source_op_num);
+                },
+                None => {
+                    tx_builder.add_transfer(
+                        &self.secret_key, &self.public_key,
+                        &transfer.destination, transfer.tokens, op_num);
+                    op_num += 1;
+                }
+            }
+        }
+        let genesis_tx = try!(tx_builder.into_transaction());
+        debug_assert!(genesis_tx.verify_signatures().is_ok());
+        create_genesis_block_at(
+            &self.public_key, &self.secret_key, genesis_tx, timestamp)
+    }
+}
+
+pub struct BlockBuilder {
+    previous: HashDigest,
+    height: u32,
+    allow_bounty: bool,
+    coinbase_pk: Option<PublicKey>,
+    transactions: Vec<Transaction>
+}
+
+impl BlockBuilder {
+    pub fn new(previous: HashDigest, height: u32) -> BlockBuilder {
+        BlockBuilder {
+            previous: previous,
+            height: height,
+            allow_bounty: false,
+            coinbase_pk: None,
+            transactions: vec![]
+        }
+    }
+
+    pub fn add_transaction(&mut self, tx: Transaction) -> &mut Self {
+        self.transactions.push(tx);
+        self
+    }
+
+    // Takes the transactions as they are, without copying them.
+    pub fn add_transactions(&mut self, transactions: Vec<Transaction>)
+                            -> &mut Self {
+        if self.transactions.is_empty() {
+            self.transactions = transactions;
+        } else {
+            self.transactions.extend(transactions.into_iter());
+        }
+        self
+    }
+
+    // Orders the transactions by id, so that building from the same set of
+    // transactions gives the same block. Transfers from one source in
+    // different transactions may end up out of op_index order, so this is
+    // only for transactions which don't depend on each other.
+    pub fn sort_transactions(&mut self) -> &mut Self {
+        self.transactions.sort_by(
+            |left, right| left.compute_id().cmp(&right.compute_id()));
+        self
+    }
+
+    // The block will start with a coinbase paying BLOCK_REWARD and the fees
+    // of its transactions to `miner_pk`.
+    pub fn set_coinbase(&mut self, miner_pk: PublicKey) -> &mut Self {
+        self.coinbase_pk = Some(miner_pk);
+        self
+    }
+
+    pub fn allow_bounty(&mut self, allow_bounty: bool) -> &mut Self {
+        self.allow_bounty = allow_bounty;
+        self
+    }
+
+    pub fn build(self, staker_sk: &SecretKey) -> IroncResult<HashedBlock> {
+        self.build_with_clock(staker_sk, &SystemClock)
+    }
+
+    pub fn build_with_clock<C: Clock>(self, staker_sk: &SecretKey, clock: &C)
+                                      -> IroncResult<HashedBlock> {
+        self.build_at(staker_sk, clock.now().sec)
+    }
+
+    fn build_at(self, staker_sk: &SecretKey, timestamp: i64)
+                -> IroncResult<HashedBlock> {
+        if !self.allow_bounty {
+            for tx in self.transactions.iter() {
+                if tx.has_bounty() {
+                    return Err(IroncError::new(
+                        "Transactions must not have a bounty set in this block."));
+                }
+            }
+        }
+        let mut block = HashedBlock::new();
+        block.set_previous_block(&self.previous);
+        block.mut_signed_block().mut_block().set_height(self.height);
+        block.mut_signed_block().mut_block().set_timestamp(timestamp);
+        let mut transactions = self.transactions;
+        if let Some(ref miner_pk) = self.coinbase_pk {
+            let fees = try!(block_fees(transactions.iter()));
+            let reward = try!(fees.checked_add(BLOCK_REWARD).ok_or(
+                IroncError::new("Block reward overflows.")));
+            transactions.insert(0, make_coinbase(miner_pk, reward, self.height));
         }
-        let genesis_tx = tx_builder.build().unwrap();
-        assert!(genesis_tx.verify_signatures().is_ok());
-        create_genesis_block(&public_key, &secret_key, genesis_tx).unwrap()
+        block.mut_signed_block().mut_block().set_transactions(
+            RepeatedField::from_vec(transactions));
+        block.reseal(staker_sk);
+        Ok(block)
     }
 }
 
+// HashedBlockExt::to_bytes() prefixes the protobuf encoding with these, so
+// stored blocks can be told apart if the format changes.
+pub const BLOCK_MAGIC: &'static [u8] = b"IRCB";
+pub const BLOCK_FORMAT_VERSION: u8 = 1;
+
+// Minted by the coinbase of a block after the genesis, on top of the fees.
+pub const BLOCK_REWARD: u64 = 50;
+
+// What a successful HashedBlockExt::verify_with_stats() checked. The block's
+// own signature is included in signatures_checked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyStats {
+    pub transactions_checked: usize,
+    pub signatures_checked: usize
+}
+
 pub trait HashedBlockExt {
+    fn check_seal_invariants(&self) -> IroncResult<()>;
+    fn coinbase(&self) -> Option<&Transaction>;
     fn compute_hash(&mut self) -> HashDigest;
     fn decode_hash(&self) -> IroncResult<HashDigest>;
     fn decode_previous(&self) -> IroncResult<HashDigest>;
     fn decode_proof(&self) -> IroncResult<HashDigest>;
     fn decode_staker_pk(&self) -> IroncResult<PublicKey>;
+    fn distribution(&self) -> IroncResult<Vec<(PublicKey, u64)>>;
+    fn from_bytes(bytes: &[u8]) -> IroncResult<Self>;
+    fn from_json(json_str: &str) -> IroncResult<Self>;
     fn get_block<'a>(&'a self) -> &'a Block;
     fn get_height(&self) -> u32;
+    fn header(&self) -> BlockHeader;
+    fn merkle_proof(&self, tx_index: usize) -> IroncResult<Vec<HashDigest>>;
+    fn mine(&mut self, difficulty: u32) -> IroncResult<HashDigest>;
+    fn rechain(&mut self, previous: &HashDigest, staker_sk: &SecretKey)
+               -> HashDigest;
+    fn reseal(&mut self, staker_sk: &SecretKey) -> HashDigest;
     fn set_previous_block(&mut self, block_hash: &HashDigest);
+    fn set_timestamp(&mut self, timestamp: Timespec);
+    fn summary(&self) -> String;
+    fn to_bytes(&self) -> IroncResult<Vec<u8>>;
+    fn timestamp(&self) -> Timespec;
+    fn to_json(&self) -> IroncResult<String>;
+    fn tx_bloom(&self) -> BloomFilter;
+    fn verify_coinbase(&self, block_reward: u64) -> IroncResult<()>;
+    fn verify_conservation(&self) -> IroncResult<()>;
+    fn verify_difficulty(&self) -> IroncResult<()>;
+    fn verify_genesis(&self) -> IroncResult<()>;
     fn verify_hash(&self) -> IroncResult<()>;
+    fn verify_header(&self) -> IroncResult<()>;
+    fn verify_is_genesis(&self) -> IroncResult<()>;
+    fn verify_merkle_root(&self) -> IroncResult<()>;
+    fn verify_no_duplicate_txes(&self) -> IroncResult<()>;
+    fn verify_size(&self, max_bytes: usize) -> IroncResult<()>;
+    fn verify_timestamp(&self, previous_timestamp: i64, max_drift: i64)
+                        -> IroncResult<()>;
+    fn verify_transaction_inclusion(&self, tx: &Transaction)
+                                    -> IroncResult<bool>;
+    fn verify_transaction_sizes(&self, max_tx_bytes: usize)
+                                -> IroncResult<()>;
     fn verify(&self) -> IroncResult<()>;
+    fn verify_with_stats(&self) -> IroncResult<VerifyStats>;
+    fn verified_transactions(&self) -> IroncResult<Vec<&Transaction>>;
+    fn verify_parallel(&self) -> IroncResult<()>;
+    fn would_be_hash(&self) -> HashDigest;
 }
 
 impl HashedBlockExt for HashedBlock {
+    // A block is sealed by first signing it, which fixes the merkle root and
+    // the staker, and then hashing it, as the hash covers the signature (see
+    // reseal). This checks the block was sealed in that order and not changed
+    // since, so builders can check their output.
+    fn check_seal_invariants(&self) -> IroncResult<()> {
+        try!(self.verify_merkle_root());
+        try!(self.get_signed_block().verify_signature());
+        self.verify_hash().map_err(|err| IroncError::with_kind(
+            err.kind(), &format!(
+                "Block hash does not cover its signature, it may have been \
+                 hashed before it was signed: {}", err)))
+    }
+
+    fn coinbase(&self) -> Option<&Transaction> {
+        self.get_block().get_transactions().iter().find(|tx| tx.is_coinbase())
+    }
+
     fn compute_hash(&mut self) -> HashDigest {
-        let hash_digest = hash_message(self.get_signed_block());
+        let hash_digest = self.would_be_hash();
         self.set_hash(hash_digest.0.to_vec());
         hash_digest
     }
@@ -89,70 +469,575 @@ impl HashedBlockExt for HashedBlock {
         self.get_block().decode_previous()
     }
 
+    // The tokens minted by a genesis block and who they went to, in the order
+    // they were added to the GenesisBuilder.
+    fn distribution(&self) -> IroncResult<Vec<(PublicKey, u64)>> {
+        try!(self.verify_is_genesis());
+        let mut distribution = vec![];
+        for tx in self.get_block().get_transactions().iter() {
+            for transfer in tx.get_commit().get_transfers().iter() {
+                let destination =
+                    try!(PublicKey::from_slice(transfer.get_destination_pk()));
+                distribution.push((destination, transfer.get_tokens()));
+            }
+        }
+        Ok(distribution)
+    }
+
     fn decode_staker_pk(&self) -> IroncResult<PublicKey> {
         self.get_block().decode_staker_pk()
     }
 
+    fn from_bytes(bytes: &[u8]) -> IroncResult<HashedBlock> {
+        let prefix_len = BLOCK_MAGIC.len() + 1;
+        if bytes.len() < prefix_len ||
+            &bytes[..BLOCK_MAGIC.len()] != BLOCK_MAGIC
+        {
+            return Err(IroncError::with_kind(
+                ErrorKind::Decode, "Not a serialized block (bad magic)."));
+        }
+        let version = bytes[BLOCK_MAGIC.len()];
+        if version != BLOCK_FORMAT_VERSION {
+            return Err(IroncError::with_kind(ErrorKind::Decode, &format!(
+                "Unsupported block format version {} (expected {}).",
+                version, BLOCK_FORMAT_VERSION)));
+        }
+        let block: HashedBlock =
+            try!(protobuf::parse_from_bytes(&bytes[prefix_len..]));
+        try!(block.verify_hash());
+        Ok(block)
+    }
+
+    fn from_json(json_str: &str) -> IroncResult<HashedBlock> {
+        let parsed = try!(Json::from_str(json_str).map_err(|err| {
+            IroncError::with_kind(ErrorKind::Decode, &format!(
+                "Could not parse block JSON: {:?}", err))
+        }));
+        let block = try!(json::decode_block(&parsed));
+        try!(block.verify_hash());
+        Ok(block)
+    }
+
     fn get_block<'a>(&'a self) -> &'a Block {
         self.get_signed_block().get_block()
     }
 
     fn get_height(&self) -> u32 { self.get_block().get_height() }
 
+    fn header(&self) -> BlockHeader {
+        let mut header = BlockHeader::new();
+        header.set_hash(self.get_hash().to_vec());
+        header.set_signed_header(self.get_signed_block().header());
+        header
+    }
+
+    fn merkle_proof(&self, tx_index: usize) -> IroncResult<Vec<HashDigest>> {
+        merkle::merkle_proof(
+            &transaction_leaves(self.get_block().get_transactions()), tx_index)
+    }
+
+    // The nonce is not covered by the signature, so mining does not need the
+    // staker's secret key.
+    fn mine(&mut self, difficulty: u32) -> IroncResult<HashDigest> {
+        // No hash has more leading zero bits than it has bits.
+        if difficulty > 8 * HASHBYTES as u32 {
+            return Err(IroncError::new(&format!(
+                "Difficulty {} is more than the {} bits of a hash.",
+                difficulty, 8 * HASHBYTES)));
+        }
+        self.mut_signed_block().set_difficulty(difficulty);
+        let mut nonce = 0u64;
+        loop {
+            self.mut_signed_block().set_nonce(nonce);
+            let block_hash = self.compute_hash();
+            if block_hash.leading_zero_bits() >= difficulty {
+                return Ok(block_hash);
+            }
+            nonce = try!(nonce.checked_add(1).ok_or(IroncError::new(
+                "Ran out of nonces before meeting the difficulty.")));
+        }
+    }
+
+    // Moves the block on top of `previous`, keeping it valid, unlike
+    // set_previous_block on its own.
+    fn rechain(&mut self, previous: &HashDigest, staker_sk: &SecretKey)
+               -> HashDigest {
+        self.set_previous_block(previous);
+        self.reseal(staker_sk)
+    }
+
+    // Brings the merkle root, signature and hash up to date after the block
+    // was modified. The hash covers the signature, so the block is signed
+    // first. A mined block keeps its difficulty and nonce and has to be mined
+    // again.
+    fn reseal(&mut self, staker_sk: &SecretKey) -> HashDigest {
+        self.mut_signed_block().sign(staker_sk);
+        self.compute_hash()
+    }
+
     fn set_previous_block(&mut self, block_hash: &HashDigest) {
         self.mut_signed_block().mut_block().set_previous(block_hash.0.to_vec())
     }
 
+    // Blocks store the timestamp as whole seconds since the Unix epoch, so
+    // any nanoseconds are dropped.
+    fn set_timestamp(&mut self, timestamp: Timespec) {
+        self.mut_signed_block().mut_block().set_timestamp(timestamp.sec)
+    }
+
+    fn summary(&self) -> String {
+        format!("Block {{ height: {}, hash: {}, previous: {}, transactions: {}, \
+                 timestamp: {} }}",
+                self.get_height(), self.get_hash().to_hex(),
+                self.get_block().get_previous().to_hex(),
+                self.get_block().get_transactions().len(),
+                self.get_block().get_timestamp())
+    }
+
+    fn timestamp(&self) -> Timespec {
+        Timespec::new(self.get_block().get_timestamp(), 0)
+    }
+
+    fn to_bytes(&self) -> IroncResult<Vec<u8>> {
+        let mut bytes = BLOCK_MAGIC.to_vec();
+        bytes.push(BLOCK_FORMAT_VERSION);
+        bytes.push_all(&try!(self.write_to_bytes()));
+        Ok(bytes)
+    }
+
+    fn to_json(&self) -> IroncResult<String> {
+        Ok(format!("{}", json::encode_block(self)))
+    }
+
+    // Lets a light client check whether the block may have a transaction,
+    // without downloading it.
+    fn tx_bloom(&self) -> BloomFilter {
+        let transactions = self.get_block().get_transactions();
+        let mut filter = BloomFilter::new(transactions.len());
+        for tx in transactions.iter() {
+            filter.insert(&tx.compute_id());
+        }
+        filter
+    }
+
+    // Unlike verify(), which accepts a block without a coinbase, this
+    // requires one in every block after the genesis.
+    fn verify_coinbase(&self, block_reward: u64) -> IroncResult<()> {
+        check_coinbase(self, block_reward, true)
+    }
+
+    // Tokens are only minted by the genesis and by a coinbase, which must
+    // mint exactly BLOCK_REWARD plus the fees, as in verify_coinbase. The
+    // other transfers credit their tokens and debit tokens plus fee, so the
+    // debits only need to be summed without overflowing.
+    fn verify_conservation(&self) -> IroncResult<()> {
+        // The genesis block is where tokens are minted.
+        if self.get_height() == 0 {
+            return Ok(());
+        }
+        let overflow = || IroncError::with_kind(
+            ErrorKind::Conservation,
+            "Block does not conserve tokens: amounts overflow.");
+        let mut debited = 0u64;
+        for tx in self.get_block().get_transactions().iter()
+            .filter(|tx| !tx.is_coinbase())
+        {
+            for transfer in tx.get_commit().get_transfers().iter() {
+                let debit = try!(transfer.get_tokens().checked_add(
+                    transfer.get_fee()).ok_or(overflow()));
+                debited = try!(debited.checked_add(debit).ok_or(overflow()));
+            }
+        }
+        check_coinbase(self, BLOCK_REWARD, false)
+    }
+
+    fn verify_difficulty(&self) -> IroncResult<()> {
+        let block_hash = try!(self.decode_hash());
+        let difficulty = self.get_signed_block().get_difficulty();
+        if block_hash.leading_zero_bits() >= difficulty { Ok(()) }
+        else { Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+            "Block hash {} does not meet the difficulty of {} leading zero bits",
+            block_hash, difficulty)))
+        }
+    }
+
+    // Called for every block when syncing, so the stored hashes are checked
+    // as raw bytes and only decoded if there is an error to report.
     fn verify_hash(&self) -> IroncResult<()> {
-        let block_hash = try!(HashDigest::from_slice(&self.get_hash()));
-        try!(self.decode_previous());
+        let stored_hash = self.get_hash();
+        if stored_hash.len() != HASHBYTES {
+            return self.decode_hash().map(|_| ());
+        }
+        if self.get_block().get_previous().len() != HASHBYTES {
+            return self.decode_previous().map(|_| ());
+        }
 
-        let computed_hash = hash_message(self.get_signed_block());
-        if computed_hash == block_hash { Ok(()) }
-        else { Err(IroncError::new(&format!(
+        let computed_hash = self.would_be_hash();
+        if computed_hash.ct_eq_slice(stored_hash) { Ok(()) }
+        else { Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
             "Block has invalid hash: {} != {} (actual)",
-            block_hash, computed_hash)))
+            stored_hash.to_hex(), computed_hash)))
+        }
+    }
+
+    fn verify_genesis(&self) -> IroncResult<()> {
+        try!(self.verify());
+        self.verify_is_genesis()
+    }
+
+    // What can be checked without the transactions, e.g. for a block which
+    // was pruned. The merkle root is trusted as signed.
+    fn verify_header(&self) -> IroncResult<()> { verify_block_header(self) }
+
+    // Only checks what distinguishes a genesis block, verify() is still
+    // needed for the rest.
+    fn verify_is_genesis(&self) -> IroncResult<()> {
+        let previous_hash = try!(self.decode_previous());
+        if !previous_hash.is_zero() {
+            return Err(IroncError::new(&format!(
+                "Genesis block has previous {} != 0.", previous_hash)));
+        }
+        if self.get_height() != 0 {
+            return Err(IroncError::new(&format!(
+                "Genesis block has height {} != 0.", self.get_height())));
+        }
+        if self.get_block().get_transactions().iter().any(|tx| tx.has_bounty()) {
+            return Err(IroncError::new(
+                "Transactions must not have a bounty set in a genesis block."));
+        }
+        Ok(())
+    }
+
+    fn verify_merkle_root(&self) -> IroncResult<()> {
+        let merkle_root = try!(self.get_block().decode_merkle_root());
+        if has_duplicate_pair(
+            &transaction_leaves(self.get_block().get_transactions()))
+        {
+            return Err(IroncError::with_kind(ErrorKind::HashMismatch,
+                "Block transactions end in a duplicated merkle subtree, \
+                 which the merkle root does not commit to."));
+        }
+        let computed_root = self.get_block().compute_merkle_root();
+        if computed_root.ct_eq(&merkle_root) { Ok(()) }
+        else { Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+            "Block has invalid merkle root: {} != {} (actual)",
+            merkle_root, computed_root)))
+        }
+    }
+
+    fn verify_no_duplicate_txes(&self) -> IroncResult<()> {
+        let mut tx_ids = HashSet::new();
+        for tx in self.get_block().get_transactions().iter() {
+            let tx_id = tx.compute_id();
+            if tx_ids.contains(&tx_id) {
+                return Err(IroncError::new(&format!(
+                    "Block contains transaction {} more than once.", tx_id)));
+            }
+            tx_ids.insert(tx_id);
+        }
+        Ok(())
+    }
+
+    // compute_size() gives the encoded size without serializing the block.
+    fn verify_size(&self, max_bytes: usize) -> IroncResult<()> {
+        let size = self.compute_size() as usize;
+        if size > max_bytes {
+            return Err(IroncError::new(&format!(
+                "Block is {} bytes, more than the maximum of {}.",
+                size, max_bytes)));
+        }
+        Ok(())
+    }
+
+    fn verify_timestamp(&self, previous_timestamp: i64, max_drift: i64)
+                        -> IroncResult<()> {
+        let timestamp = self.get_block().get_timestamp();
+        if timestamp < previous_timestamp {
+            return Err(IroncError::new(&format!(
+                "Block timestamp {} is earlier than its parent's ({}).",
+                timestamp, previous_timestamp)));
+        }
+        let now = now_utc().to_timespec().sec;
+        if timestamp > now + max_drift {
+            return Err(IroncError::new(&format!(
+                "Block timestamp {} is more than {}s in the future (now {}).",
+                timestamp, max_drift, now)));
+        }
+        Ok(())
+    }
+
+    // Whether `tx` is in the block, checked against the signed merkle root
+    // and with only the transaction's own signatures verified, which is
+    // cheaper than verify(). An error if it is in the block but is invalid.
+    fn verify_transaction_inclusion(&self, tx: &Transaction)
+                                    -> IroncResult<bool> {
+        let tx_id = tx.compute_id();
+        let index = match self.get_block().get_transactions().iter()
+            .position(|included| included.compute_id() == tx_id)
+        {
+            Some(index) => index,
+            None => return Ok(false)
+        };
+        // Unlike the id, the merkle leaves cover the signatures too.
+        let leaf = hash_message(tx);
+        let merkle_root = try!(self.get_block().decode_merkle_root());
+        let proof = try!(self.merkle_proof(index));
+        let num_leaves = self.get_block().get_transactions().len();
+        if !merkle::verify_merkle_proof(&leaf, index, num_leaves, &proof,
+                                        &merkle_root) {
+            return Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+                "Transaction {} is not committed to by the merkle root {}.",
+                tx_id, merkle_root)));
+        }
+        try!(self.get_signed_block().verify_signature());
+        if tx.is_coinbase() {
+            try!(check_coinbase(self, BLOCK_REWARD, false));
+        } else {
+            try!(tx.verify_op_sequence());
+            try!(tx.verify_signatures());
+            try!(tx.verify_bounty());
         }
+        Ok(true)
+    }
+
+    // Caps each transaction on its own, so one can't take up most of a block
+    // which is within verify_size().
+    fn verify_transaction_sizes(&self, max_tx_bytes: usize)
+                                -> IroncResult<()> {
+        for (index, tx) in self.get_block().get_transactions().iter()
+            .enumerate()
+        {
+            let size = tx.compute_size() as usize;
+            if size > max_tx_bytes {
+                return Err(IroncError::new(&format!(
+                    "Transaction {} ({}) is {} bytes, more than the maximum \
+                     of {}.", index, tx.compute_id(), size, max_tx_bytes)));
+            }
+        }
+        Ok(())
     }
 
     fn verify(&self) -> IroncResult<()> {
+        try!(self.verify_with_stats());
+        Ok(())
+    }
+
+    fn verify_with_stats(&self) -> IroncResult<VerifyStats> {
+        verify_block(self, BLOCK_REWARD)
+    }
+
+    fn verified_transactions(&self) -> IroncResult<Vec<&Transaction>> {
+        let mut verified = vec![];
+        for (index, tx) in self.get_block().get_transactions().iter()
+            .enumerate()
+        {
+            // The coinbase is checked by check_coinbase instead.
+            let verified_tx = if tx.is_coinbase() { Ok(()) } else {
+                tx.verify_op_sequence()
+                    .and_then(|_| tx.verify_signatures())
+                    .and_then(|_| tx.verify_bounty())
+            };
+            match verified_tx {
+                Ok(_) => verified.push(tx),
+                Err(err) => return Err(IroncError::with_kind(
+                    err.kind(),
+                    &format!("Transaction {} ({}) failed verification: {}",
+                             index, tx.compute_id(), err)))
+            }
+        }
+        Ok(verified)
+    }
+
+    fn verify_parallel(&self) -> IroncResult<()> {
         try!(self.verify_hash());
+        try!(self.verify_difficulty());
+        try!(self.verify_merkle_root());
+        try!(self.verify_no_duplicate_txes());
         try!(self.get_signed_block().verify_signature());
-        let txes = self.get_block().get_transactions();
-        for tx in txes { try!(tx.verify_signatures()); }
-        Ok(())
+        try!(check_coinbase(self, BLOCK_REWARD, false));
+        for tx in self.get_block().get_transactions().iter()
+            .filter(|tx| !tx.is_coinbase())
+        {
+            try!(tx.verify_op_sequence());
+            try!(tx.verify_bounty());
+        }
+        verify_transactions_parallel(self.get_block().get_transactions())
+    }
+
+    // The hash compute_hash() would set, leaving the stored one as it is.
+    fn would_be_hash(&self) -> HashDigest {
+        self.get_signed_block().compute_hash()
+    }
+}
+
+pub struct CachedBlock {
+    block: HashedBlock,
+    cached_hash: RefCell<Option<HashDigest>>
+}
+
+impl CachedBlock {
+    pub fn new(block: HashedBlock) -> CachedBlock {
+        CachedBlock { block: block, cached_hash: RefCell::new(None) }
+    }
+
+    pub fn get_hashed_block(&self) -> &HashedBlock { &self.block }
+
+    // Any mutation goes through here, so that's where the cache is dropped.
+    pub fn mut_signed_block(&mut self) -> &mut SignedBlock {
+        *self.cached_hash.borrow_mut() = None;
+        self.block.mut_signed_block()
+    }
+
+    pub fn hash(&self) -> HashDigest {
+        let mut cached_hash = self.cached_hash.borrow_mut();
+        if cached_hash.is_none() {
+            *cached_hash = Some(self.block.get_signed_block().compute_hash());
+        }
+        cached_hash.as_ref().unwrap().clone()
+    }
+
+    pub fn compute_hash(&mut self) -> HashDigest {
+        let block_hash = self.hash();
+        self.block.set_hash(block_hash.0.to_vec());
+        block_hash
+    }
+
+    pub fn verify_hash(&self) -> IroncResult<()> {
+        let block_hash = try!(self.block.decode_hash());
+        try!(self.block.decode_previous());
+        let computed_hash = self.hash();
+        if computed_hash.ct_eq(&block_hash) { Ok(()) }
+        else { Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+            "Block has invalid hash: {} != {} (actual)",
+            block_hash, computed_hash)))
+        }
     }
+
+    pub fn into_hashed_block(self) -> HashedBlock { self.block }
 }
 
 pub trait SignedBlockExt {
+    fn compute_hash(&self) -> HashDigest;
+    fn compute_hash_with<H: HashFunction>(&self) -> HashDigest;
     fn decode_signature(&self) -> IroncResult<Signature>;
+    fn header(&self) -> SignedBlock;
     fn sign(&mut self, secret_key: &SecretKey);
+    fn sign_checked(&mut self, secret_key: &SecretKey) -> IroncResult<()>;
     fn verify_signature(&self) -> IroncResult<()>;
 }
 
 impl SignedBlockExt for SignedBlock {
+    fn compute_hash(&self) -> HashDigest { self.compute_hash_with::<Sha512>() }
+
+    fn compute_hash_with<H: HashFunction>(&self) -> HashDigest {
+        hash_message_with::<H, _>(&self.header())
+    }
+
     fn decode_signature(&self) -> IroncResult<Signature> {
         Signature::from_slice(self.get_signature())
     }
 
+    fn header(&self) -> SignedBlock {
+        let mut header = SignedBlock::new();
+        if self.has_signature() {
+            header.set_signature(self.get_signature().to_vec());
+        }
+        if self.has_block() { header.set_block(self.get_block().header()); }
+        if self.has_difficulty() { header.set_difficulty(self.get_difficulty()); }
+        if self.has_nonce() { header.set_nonce(self.get_nonce()); }
+        header
+    }
+
+    // Only the header is signed, the merkle root commits to the transactions.
     fn sign(&mut self, secret_key: &SecretKey) {
-        let signature = sign_message(secret_key, self.get_block());
+        let merkle_root = self.get_block().compute_merkle_root();
+        self.mut_block().set_merkle_root(merkle_root.0.to_vec());
+        self.mut_block().set_staker_pk(secret_key.public_key().0.to_vec());
+        let signature = self.get_block().signature_scheme()
+            .sign_message(secret_key, &self.get_block().header());
         self.set_signature(signature.0.to_vec());
     }
 
+    // As sign, but checks the signature against the public key which is
+    // embedded in `secret_key` and recorded as the staker, e.g. to catch a
+    // secret key whose halves don't belong together.
+    fn sign_checked(&mut self, secret_key: &SecretKey) -> IroncResult<()> {
+        self.sign(secret_key);
+        self.verify_signature().map_err(|err| IroncError::with_kind(
+            ErrorKind::SignatureInvalid, &format!(
+                "Secret key does not match its public key {}: {}",
+                secret_key.public_key(), err)))
+    }
+
     fn verify_signature(&self) -> IroncResult<()> {
         let public_key = try!(self.get_block().decode_staker_pk());
         let signature = try!(self.decode_signature());
-        verify_signed_message(&public_key, self.get_block(), &signature)
+        self.get_block().signature_scheme().verify_signed_message(
+            &public_key, &self.get_block().header(), &signature)
+    }
+}
+
+pub trait BlockHeaderExt {
+    fn compute_hash(&mut self) -> HashDigest;
+    fn decode_hash(&self) -> IroncResult<HashDigest>;
+    fn from_bytes(bytes: &[u8]) -> IroncResult<Self>;
+    fn to_bytes(&self) -> IroncResult<Vec<u8>>;
+    fn verify_hash(&self) -> IroncResult<()>;
+}
+
+impl BlockHeaderExt for BlockHeader {
+    fn compute_hash(&mut self) -> HashDigest {
+        let hash_digest = hash_message(self.get_signed_header());
+        self.set_hash(hash_digest.0.to_vec());
+        hash_digest
+    }
+
+    fn decode_hash(&self) -> IroncResult<HashDigest> {
+        HashDigest::from_slice(self.get_hash())
+    }
+
+    fn from_bytes(bytes: &[u8]) -> IroncResult<BlockHeader> {
+        let header: BlockHeader = try!(protobuf::parse_from_bytes(bytes));
+        try!(header.verify_hash());
+        Ok(header)
+    }
+
+    fn to_bytes(&self) -> IroncResult<Vec<u8>> {
+        Ok(try!(self.write_to_bytes()))
+    }
+
+    fn verify_hash(&self) -> IroncResult<()> {
+        let header_hash = try!(self.decode_hash());
+        if self.get_signed_header().get_block().get_transactions().len() > 0 {
+            return Err(IroncError::new("Block header contains transactions."));
+        }
+        let computed_hash = hash_message(self.get_signed_header());
+        if computed_hash.ct_eq(&header_hash) { Ok(()) }
+        else { Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+            "Block header has invalid hash: {} != {} (actual)",
+            header_hash, computed_hash)))
+        }
     }
 }
 
 pub trait BlockExt {
+    fn compute_merkle_root(&self) -> HashDigest;
+    fn decode_merkle_root(&self) -> IroncResult<HashDigest>;
     fn decode_previous(&self) -> IroncResult<HashDigest>;
     fn decode_staker_pk(&self) -> IroncResult<PublicKey>;
+    fn header(&self) -> Block;
+    fn signature_scheme(&self) -> SignatureScheme;
 }
 
 impl BlockExt for Block {
+    fn compute_merkle_root(&self) -> HashDigest {
+        merkle_root(&transaction_leaves(self.get_transactions()))
+    }
+
+    fn decode_merkle_root(&self) -> IroncResult<HashDigest> {
+        HashDigest::from_slice(self.get_merkle_root())
+    }
+
     fn decode_previous(&self) -> IroncResult<HashDigest> {
         HashDigest::from_slice(self.get_previous())
     }
@@ -160,31 +1045,174 @@ impl BlockExt for Block {
     fn decode_staker_pk(&self) -> IroncResult<PublicKey> {
         PublicKey::from_slice(self.get_staker_pk())
     }
+
+    // Every field except for the transactions.
+    fn header(&self) -> Block {
+        let mut header = Block::new();
+        if self.has_staker_pk() {
+            header.set_staker_pk(self.get_staker_pk().to_vec());
+        }
+        if self.has_previous() {
+            header.set_previous(self.get_previous().to_vec());
+        }
+        if self.has_timestamp() { header.set_timestamp(self.get_timestamp()); }
+        if self.has_height() { header.set_height(self.get_height()); }
+        if self.has_target_hash() {
+            header.set_target_hash(self.get_target_hash().to_vec());
+        }
+        if self.has_merkle_root() {
+            header.set_merkle_root(self.get_merkle_root().to_vec());
+        }
+        if self.has_signature_scheme() {
+            header.set_signature_scheme(self.get_signature_scheme());
+        }
+        header
+    }
+
+    // Which scheme the staker signed the block with, ed25519 if unset.
+    fn signature_scheme(&self) -> SignatureScheme {
+        if !self.has_signature_scheme() {
+            return DEFAULT_SIGNATURE_SCHEME;
+        }
+        match self.get_signature_scheme() {
+            Block_SignatureScheme::ED25519 => SignatureScheme::Ed25519
+        }
+    }
 }
 
-pub trait BlockWithDiffExt {
-    fn decode_hash(&self) -> IroncResult<HashDigest>;
+pub trait BlockPatchExt {
     fn decode_previous(&self) -> IroncResult<HashDigest>;
-    fn get_height(&self) -> u32;
+    fn set_previous_block(&mut self, block_hash: &HashDigest);
 }
 
-impl BlockWithDiffExt for BlockWithDiff {
-    fn decode_hash(&self) -> IroncResult<HashDigest> {
-        self.get_hashed_block().decode_hash()
-    }
-
+impl BlockPatchExt for BlockPatch {
     fn decode_previous(&self) -> IroncResult<HashDigest> {
-        self.get_hashed_block().decode_previous()
+        self.get_header().get_block().decode_previous()
     }
 
-    fn get_height(&self) -> u32 {
-        self.get_hashed_block().get_height()
+    fn set_previous_block(&mut self, block_hash: &HashDigest) {
+        self.mut_header().mut_block().set_previous(block_hash.0.to_vec())
     }
 }
 
-/*****  Tests  *****/
+// The checks behind HashedBlockExt::verify_header(), callable without the
+// extension traits in scope.
+pub fn verify_block_header(block: &HashedBlock) -> IroncResult<()> {
+    try!(block.verify_hash());
+    try!(block.verify_difficulty());
+    block.get_signed_block().verify_signature()
+}
 
-use crypto::{hash};
+// The checks behind HashedBlockExt::verify_with_stats(), which passes
+// BLOCK_REWARD as the most a coinbase may pay on top of the fees. The block
+// is checked on its own, nothing about its place in a chain.
+pub fn verify_block(block: &HashedBlock, block_reward: u64)
+                    -> IroncResult<VerifyStats> {
+    try!(block.verify_hash());
+    try!(block.verify_difficulty());
+    try!(block.verify_merkle_root());
+    try!(block.verify_no_duplicate_txes());
+    try!(block.get_signed_block().verify_signature());
+    try!(check_coinbase(block, block_reward, false));
+    let transactions = try!(block.verified_transactions());
+    let mut stats = VerifyStats {
+        transactions_checked: transactions.len(),
+        signatures_checked: 1
+    };
+    for tx in transactions.iter().filter(|tx| !tx.is_coinbase()) {
+        stats.signatures_checked += tx.get_commit().get_transfers().len();
+        if tx.has_bounty() { stats.signatures_checked += 1; }
+    }
+    Ok(stats)
+}
+
+// A patch carries the header of the next block together with the changes
+// to get its transactions from the ones of the block it builds on: drop the
+// `removed` indices, then append `added`.
+pub fn apply_patch(block: &HashedBlock, patch: &BlockPatch)
+                   -> IroncResult<HashedBlock> {
+    let block_hash = try!(block.decode_hash());
+    let patch_previous = try!(patch.decode_previous());
+    if patch_previous != block_hash {
+        return Err(IroncError::new(&format!(
+            "Block patch has previous {} != {} (base block)",
+            patch_previous, block_hash)));
+    }
+    let base_transactions = block.get_block().get_transactions();
+    let mut removed = HashSet::new();
+    for &index in patch.get_removed().iter() {
+        if index as usize >= base_transactions.len() {
+            return Err(IroncError::new(&format!(
+                "Block patch removes transaction {} but the base block has {}",
+                index, base_transactions.len())));
+        }
+        removed.insert(index as usize);
+    }
+
+    let mut patched = HashedBlock::new();
+    patched.set_signed_block(patch.get_header().clone());
+    {
+        let transactions =
+            patched.mut_signed_block().mut_block().mut_transactions();
+        transactions.clear();
+        for (index, tx) in base_transactions.iter().enumerate() {
+            if !removed.contains(&index) { transactions.push(tx.clone()); }
+        }
+        for tx in patch.get_added().iter() { transactions.push(tx.clone()); }
+    }
+    patched.compute_hash();
+    Ok(patched)
+}
+
+// Computes the patch that `apply_patch` turns `from` into `to`: the
+// transactions of `from` are matched in order against those of `to`, the
+// unmatched ones are removed and the rest of `to` is appended. `to` is
+// expected to build on `from`, otherwise applying the patch will fail.
+pub fn diff_blocks(from: &HashedBlock, to: &HashedBlock) -> BlockPatch {
+    let from_transactions = from.get_block().get_transactions();
+    let to_transactions = to.get_block().get_transactions();
+    let mut patch = BlockPatch::new();
+    patch.set_header(to.get_signed_block().clone());
+    patch.mut_header().mut_block().clear_transactions();
+
+    let mut matched = 0;
+    for (index, tx) in from_transactions.iter().enumerate() {
+        if matched < to_transactions.len() && *tx == to_transactions[matched] {
+            matched += 1;
+        } else {
+            patch.mut_removed().push(index as u32);
+        }
+    }
+    for tx in to_transactions[matched..].iter() {
+        patch.mut_added().push(tx.clone());
+    }
+    patch
+}
+
+pub trait BlockWithDiffExt {
+    fn decode_hash(&self) -> IroncResult<HashDigest>;
+    fn decode_previous(&self) -> IroncResult<HashDigest>;
+    fn get_height(&self) -> u32;
+}
+
+impl BlockWithDiffExt for BlockWithDiff {
+    fn decode_hash(&self) -> IroncResult<HashDigest> {
+        self.get_hashed_block().decode_hash()
+    }
+
+    fn decode_previous(&self) -> IroncResult<HashDigest> {
+        self.get_hashed_block().decode_previous()
+    }
+
+    fn get_height(&self) -> u32 {
+        self.get_hashed_block().get_height()
+    }
+}
+
+/*****  Tests  *****/
+
+use chain::verify_chain;
+use crypto::{hash};
 
 #[test]
 fn test_create_genesis_empty() {
@@ -234,14 +1262,1590 @@ fn test_hashed_block_hash_integrity() {
     assert!(hashed_block.verify_hash().is_ok());
 }
 
-// #[test]
-// fn test_hashed_block_sign_integrity() {
-//     let mut hashed_block = HashedBlock::new();
-//     hashed_block.mut_signed_block().mut_mut_block().set_hash(hash("test1"));
+#[test]
+fn test_hashed_block_sign_integrity() {
+    let (pk, sk) = gen_keypair();
+    let mut hashed_block = HashedBlock::new();
+    hashed_block.mut_signed_block().mut_block().set_staker_pk(pk.0.to_vec());
+    hashed_block.mut_signed_block().mut_block()
+        .set_previous(hash(b"test1").0.to_vec());
+    assert!(hashed_block.get_signed_block().verify_signature().is_err());
+    hashed_block.mut_signed_block().sign(&sk);
+    assert!(hashed_block.get_signed_block().verify_signature().is_ok());
+
+    let mut signature = hashed_block.get_signed_block().get_signature().to_vec();
+    signature[0] ^= 0xff;
+    hashed_block.mut_signed_block().set_signature(signature);
+    assert!(hashed_block.get_signed_block().verify_signature().is_err());
+
+    hashed_block.mut_signed_block().set_signature(vec![]);
+    assert!(hashed_block.get_signed_block().verify_signature().is_err());
+}
+
+#[test]
+fn test_hashed_block_verify_rejects_forged_signature() {
+    let (pk, sk) = gen_keypair();
+    let mut genesis = create_genesis_block(&pk, &sk, Transaction::new()).unwrap();
+    assert!(genesis.verify().is_ok());
+
+    let (_, forger_sk) = gen_keypair();
+    genesis.mut_signed_block().sign(&forger_sk);
+    genesis.mut_signed_block().mut_block().set_staker_pk(pk.0.to_vec());
+    genesis.compute_hash();
+    assert!(genesis.verify_hash().is_ok());
+    assert!(genesis.verify().is_err());
+}
+
+#[test]
+fn test_signed_block_sign_sets_staker_pk() {
+    let (pk, sk) = gen_keypair();
+    let mut signed_block = SignedBlock::new();
+    signed_block.mut_block().set_previous(hash(b"test1").0.to_vec());
+    signed_block.sign(&sk);
+    assert_eq!(pk, signed_block.get_block().decode_staker_pk().unwrap());
+    assert!(signed_block.verify_signature().is_ok());
+}
+
+#[test]
+fn test_signed_block_verify_with_different_key() {
+    let (_, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut signed_block = SignedBlock::new();
+    signed_block.sign(&sk1);
+    signed_block.mut_block().set_staker_pk(pk2.0.to_vec());
+    assert!(signed_block.verify_signature().is_err());
+}
+
+#[test]
+fn test_signed_block_verify_corrupted_staker_pk() {
+    let (_, sk) = gen_keypair();
+    let mut signed_block = SignedBlock::new();
+    signed_block.sign(&sk);
+
+    let mut staker_pk = signed_block.get_block().get_staker_pk().to_vec();
+    staker_pk.pop();
+    signed_block.mut_block().set_staker_pk(staker_pk.clone());
+    assert!(signed_block.verify_signature().is_err());
+
+    staker_pk.push(0);
+    staker_pk.push(0);
+    signed_block.mut_block().set_staker_pk(staker_pk);
+    assert!(signed_block.verify_signature().is_err());
+
+    signed_block.mut_block().clear_staker_pk();
+    assert!(signed_block.verify_signature().is_err());
+}
+
+#[test]
+fn test_hashed_block_bytes_round_trip() {
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1, 100);
+    builder.add_transfer(pk2, 200);
+    let genesis = builder.build().unwrap();
+
+    let genesis_bytes = genesis.to_bytes().unwrap();
+    let loaded = HashedBlock::from_bytes(&genesis_bytes).unwrap();
+    assert_eq!(genesis.decode_hash().unwrap(), loaded.decode_hash().unwrap());
+    assert!(genesis.get_block().get_transactions() ==
+            loaded.get_block().get_transactions());
+    assert!(genesis == loaded);
+}
+
+#[test]
+fn test_hashed_block_from_bytes_rejects_corrupt_hash() {
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let mut corrupt = genesis.clone();
+    corrupt.set_hash(hash(b"not the hash").0.to_vec());
+    let corrupt_bytes = corrupt.to_bytes().unwrap();
+    assert!(HashedBlock::from_bytes(&corrupt_bytes).is_err());
+    assert!(HashedBlock::from_bytes(&[0xff, 0xff, 0xff]).is_err());
+}
+
+#[test]
+fn test_hashed_block_merkle_root() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder1 = TransactionBuilder::new();
+    tx_builder1.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let mut tx_builder2 = TransactionBuilder::new();
+    tx_builder2.add_transfer(&sk2, &pk2, &pk1, 5, 0);
+
+    let mut hashed_block = HashedBlock::new();
+    hashed_block.mut_signed_block().mut_block().mut_transactions()
+        .push(tx_builder1.build().unwrap());
+    hashed_block.mut_signed_block().mut_block().mut_transactions()
+        .push(tx_builder2.build().unwrap());
+    assert!(hashed_block.verify_merkle_root().is_err());
+    hashed_block.mut_signed_block().sign(&sk1);
+    hashed_block.compute_hash();
+    assert!(hashed_block.verify_merkle_root().is_ok());
+    assert!(hashed_block.verify().is_ok());
+
+    hashed_block.mut_signed_block().mut_block().mut_transactions()[1]
+        .mut_commit().mut_transfers()[0].set_tokens(6);
+    assert!(hashed_block.verify_merkle_root().is_err());
+    assert!(hashed_block.verify().is_err());
+}
+
+#[test]
+fn test_hashed_block_merkle_root_empty() {
+    let (_, sk1) = gen_keypair();
+    let (_, sk2) = gen_keypair();
+    let mut empty_block1 = HashedBlock::new();
+    let mut empty_block2 = HashedBlock::new();
+    empty_block1.mut_signed_block().sign(&sk1);
+    empty_block2.mut_signed_block().sign(&sk2);
+    assert!(hash(b"") ==
+            empty_block1.get_block().decode_merkle_root().unwrap());
+    assert!(empty_block1.get_block().get_merkle_root() ==
+            empty_block2.get_block().get_merkle_root());
+}
+
+#[test]
+fn test_hashed_block_merkle_proof() {
+    use crypto::hash_message;
+    use merkle::verify_merkle_proof;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut hashed_block = HashedBlock::new();
+    for tokens in range(1, 6) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, tokens, 0);
+        hashed_block.mut_signed_block().mut_block().mut_transactions()
+            .push(tx_builder.build().unwrap());
+    }
+    hashed_block.mut_signed_block().sign(&sk1);
+    let root = hashed_block.get_block().decode_merkle_root().unwrap();
+    for index in range(0, 5) {
+        let tx_hash = hash_message(&hashed_block.get_block().get_transactions()[index]);
+        let proof = hashed_block.merkle_proof(index).unwrap();
+        assert!(verify_merkle_proof(&tx_hash, index, 5, &proof, &root));
+    }
+    assert!(hashed_block.merkle_proof(5).is_err());
+}
+
+#[test]
+fn test_hashed_block_verify_parallel() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut hashed_block = HashedBlock::new();
+    for op_index in range(0, 500) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 1, op_index);
+        hashed_block.mut_signed_block().mut_block().mut_transactions()
+            .push(tx_builder.build().unwrap());
+    }
+    hashed_block.mut_signed_block().sign(&sk1);
+    hashed_block.compute_hash();
+    assert!(hashed_block.verify().is_ok());
+    assert!(hashed_block.verify_parallel().is_ok());
+
+    {
+        let signature = hashed_block.mut_signed_block().mut_block()
+            .mut_transactions()[317].mut_signatures()[0].mut_payload();
+        signature[0] ^= 0xff;
+    }
+    hashed_block.mut_signed_block().sign(&sk1);
+    hashed_block.compute_hash();
+    assert!(hashed_block.verify().is_err());
+    assert!(hashed_block.verify_parallel().is_err());
+    assert_eq!(hashed_block.verify(), hashed_block.verify_parallel());
+}
+
+#[test]
+fn test_cached_block_invalidates_on_mutation() {
+    let mut hashed_block = HashedBlock::new();
+    hashed_block.mut_signed_block().mut_block()
+        .set_previous(hash(b"test1").0.to_vec());
+    hashed_block.compute_hash();
+    let mut cached = CachedBlock::new(hashed_block.clone());
+    assert!(cached.verify_hash().is_ok());
+    assert_eq!(hashed_block.decode_hash().unwrap(), cached.hash());
+
+    cached.mut_signed_block().mut_block()
+        .set_previous(hash(b"test2").0.to_vec());
+    assert!(cached.hash() != hashed_block.decode_hash().unwrap());
+    assert!(cached.verify_hash().is_err());
+
+    let new_hash = cached.compute_hash();
+    assert!(cached.verify_hash().is_ok());
+    let mut rehashed = cached.into_hashed_block();
+    assert_eq!(new_hash, rehashed.decode_hash().unwrap());
+    assert_eq!(new_hash, rehashed.compute_hash());
+}
+
+fn make_transfer_transaction(source: &PublicKey, destination: &PublicKey,
+                             tokens: u64, fee: u64) -> Transaction {
+    use ironcoin_pb::Transfer;
 
-//     assert!(hashed_block.verify_hash().is_err());
-//     hashed_block.compute_hash();
-//     assert!(hashed_block.verify_hash().is_ok());
-//     println!("Block hash: {}",
-//             &hashed_block.get_hash().to_base64(base64::STANDARD));
-// }
+    let mut transfer = Transfer::new();
+    transfer.set_source_pk(source.0.to_vec());
+    transfer.set_destination_pk(destination.0.to_vec());
+    transfer.set_tokens(tokens);
+    transfer.set_fee(fee);
+    let mut transaction = Transaction::new();
+    transaction.mut_commit().mut_transfers().push(transfer);
+    transaction
+}
+
+#[test]
+fn test_hashed_block_verify_conservation() {
+    use std::u64;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 10, 2, 0);
+    let mut balanced = HashedBlock::new();
+    balanced.mut_signed_block().mut_block().set_height(1);
+    balanced.mut_signed_block().mut_block().mut_transactions()
+        .push(tx_builder.build().unwrap());
+    assert!(balanced.verify_conservation().is_ok());
+
+    let mut inflationary = HashedBlock::new();
+    inflationary.mut_signed_block().mut_block().set_height(1);
+    inflationary.mut_signed_block().mut_block().mut_transactions().push(
+        make_transfer_transaction(&pk1, &pk2, u64::MAX, 1));
+    assert!(inflationary.verify_conservation().is_err());
+
+    let mut genesis = inflationary.clone();
+    genesis.mut_signed_block().mut_block().set_height(0);
+    assert!(genesis.verify_conservation().is_ok());
+
+    // A coinbase must mint exactly the reward and the fees of the block;
+    // minting one token more is inflationary without any amount overflowing.
+    for &(minted, conserves) in [(BLOCK_REWARD + 1, false),
+                                 (BLOCK_REWARD + 2, true),
+                                 (BLOCK_REWARD + 3, false)].iter() {
+        let mut block = balanced.clone();
+        let mut transactions = vec![make_coinbase(&pk2, minted, 1)];
+        transactions.push_all(balanced.get_block().get_transactions());
+        block.mut_signed_block().mut_block().set_transactions(
+            RepeatedField::from_vec(transactions));
+        assert_eq!(conserves, block.verify_conservation().is_ok());
+        if !conserves {
+            assert_eq!(ErrorKind::Conservation,
+                       block.verify_conservation().unwrap_err().kind());
+        }
+    }
+}
+
+#[test]
+fn test_genesis_builder_with_keypair() {
+    use std::collections::HashMap;
+    use balance::LedgerReader;
+    use blocktree::BlockTreeStore;
+
+    let (mint_pk, mint_sk) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::with_keypair(mint_pk.clone(), mint_sk.clone());
+    assert_eq!(mint_pk, *builder.public_key());
+    assert_eq!(mint_sk, *builder.secret_key());
+    builder.add_transfer(mint_pk.clone(), 100);
+    let genesis = builder.build().unwrap();
+    assert_eq!(mint_pk, genesis.decode_staker_pk().unwrap());
+
+    let kv_store = HashMap::<Vec<u8>, Vec<u8>>::new();
+    let blocktree = BlockTreeStore::new(kv_store, Some(genesis)).unwrap();
+    assert_eq!(100, blocktree.get_balance(&mint_pk).unwrap().get_tokens());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&mint_sk, &mint_pk, &pk2, 60, 0);
+    let spend = tx_builder.build().unwrap();
+    let mut snapshot = blocktree.snapshot();
+    snapshot.apply_transaction(&spend).unwrap();
+    assert_eq!(40, snapshot.get_balance(&mint_pk).unwrap().get_tokens());
+    assert_eq!(60, snapshot.get_balance(&pk2).unwrap().get_tokens());
+}
+
+#[test]
+fn test_genesis_builder_random_keypair() {
+    let builder = GenesisBuilder::new();
+    let public_key = builder.public_key().clone();
+    assert_eq!(public_key, builder.secret_key().public_key());
+    let genesis = builder.build().unwrap();
+    assert_eq!(public_key, genesis.decode_staker_pk().unwrap());
+}
+
+#[test]
+fn test_genesis_builder_build_deterministic() {
+    let make_genesis = |seed: &[u8]| {
+        let (pk1, _) = gen_keypair_from_seed(b"recipient1");
+        let (pk2, _) = gen_keypair_from_seed(b"recipient2");
+        let mut builder = GenesisBuilder::new();
+        builder.add_transfer(pk1, 1000);
+        builder.add_transfer(pk2, 2000);
+        builder.build_deterministic(seed, 1420070400).unwrap()
+    };
+    let genesis1 = make_genesis(b"fixture");
+    let genesis2 = make_genesis(b"fixture");
+    assert!(genesis1.verify().is_ok());
+    assert_eq!(1420070400, genesis1.get_block().get_timestamp());
+    assert_eq!(genesis1.decode_hash().unwrap(), genesis2.decode_hash().unwrap());
+    assert_eq!(genesis1.to_bytes().unwrap(), genesis2.to_bytes().unwrap());
+    assert!(genesis1.decode_hash().unwrap() !=
+            make_genesis(b"other fixture").decode_hash().unwrap());
+}
+
+#[test]
+fn test_apply_patch() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let transactions: Vec<Transaction> = range(0, 4).map(|op_index| {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        tx_builder.build().unwrap()
+    }).collect();
+
+    let mut base = HashedBlock::new();
+    base.set_previous_block(&HashDigest::from_u64(0));
+    for tx in transactions[..3].iter() {
+        base.mut_signed_block().mut_block().mut_transactions().push(tx.clone());
+    }
+    base.mut_signed_block().sign(&sk1);
+    let base_hash = base.compute_hash();
+
+    let mut next = HashedBlock::new();
+    next.set_previous_block(&base_hash);
+    next.mut_signed_block().mut_block().set_height(1);
+    for &index in [0, 2, 3].iter() {
+        next.mut_signed_block().mut_block().mut_transactions()
+            .push(transactions[index].clone());
+    }
+    next.mut_signed_block().sign(&sk1);
+    next.compute_hash();
+
+    let mut patch = BlockPatch::new();
+    patch.set_header(next.get_signed_block().clone());
+    patch.mut_header().mut_block().clear_transactions();
+    patch.mut_removed().push(1);
+    patch.mut_added().push(transactions[3].clone());
+    assert_eq!(base_hash, patch.decode_previous().unwrap());
+
+    let patched = apply_patch(&base, &patch).unwrap();
+    assert!(patched == next);
+    assert!(patched.verify().is_ok());
+
+    patch.mut_removed().push(3);
+    assert!(apply_patch(&base, &patch).is_err());
+}
+
+#[test]
+fn test_apply_patch_mismatched_previous() {
+    let (_, sk) = gen_keypair();
+    let mut base = HashedBlock::new();
+    base.mut_signed_block().sign(&sk);
+    base.compute_hash();
+
+    let mut patch = BlockPatch::new();
+    patch.set_previous_block(&hash(b"some other block"));
+    assert!(apply_patch(&base, &patch).is_err());
+    patch.set_previous_block(&base.decode_hash().unwrap());
+    assert!(apply_patch(&base, &patch).is_ok());
+}
+
+#[test]
+fn test_diff_blocks_round_trip() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let transactions: Vec<Transaction> = range(0, 6).map(|op_index| {
+        make_transfer_transaction(&pk1, &pk2, 10 + op_index, 0)
+    }).collect();
+    let make_block = |previous: &HashDigest, indices: &[usize]| {
+        let mut block = HashedBlock::new();
+        block.set_previous_block(previous);
+        for &index in indices.iter() {
+            block.mut_signed_block().mut_block().mut_transactions()
+                .push(transactions[index].clone());
+        }
+        block.mut_signed_block().sign(&sk1);
+        block.compute_hash();
+        block
+    };
+
+    let from = make_block(&HashDigest::from_u64(0), &[0, 1, 2, 3]);
+    let from_hash = from.decode_hash().unwrap();
+    let cases: Vec<Vec<usize>> = vec![
+        vec![], vec![0, 1, 2, 3], vec![1, 3, 4], vec![3, 2, 1, 0],
+        vec![4, 5], vec![0, 2, 5]];
+    for indices in cases.iter() {
+        let to = make_block(&from_hash, &indices[..]);
+        let patch = diff_blocks(&from, &to);
+        assert_eq!(0, patch.get_header().get_block().get_transactions().len());
+        assert!(patch.get_added().len() <= indices.len());
+        assert!(apply_patch(&from, &patch).unwrap() == to);
+    }
+
+    let unrelated = make_block(&HashDigest::from_u64(1), &[0]);
+    assert!(apply_patch(&from, &diff_blocks(&from, &unrelated)).is_err());
+}
+
+#[test]
+fn test_block_builder_two_block_chain() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut genesis_builder = GenesisBuilder::new();
+    genesis_builder.add_transfer(pk1.clone(), 1000);
+    let genesis = genesis_builder.build().unwrap();
+    let genesis_hash = genesis.decode_hash().unwrap();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0);
+    let mut block_builder = BlockBuilder::new(genesis_hash.clone(), 1);
+    block_builder.add_transaction(tx_builder.build().unwrap());
+    let block = block_builder.build(&sk1).unwrap();
+
+    assert!(genesis.verify().is_ok());
+    assert!(block.verify().is_ok());
+    assert_eq!(genesis_hash, block.decode_previous().unwrap());
+    assert_eq!(1, block.get_height());
+    assert_eq!(pk1, block.decode_staker_pk().unwrap());
+    assert_eq!(1, block.get_block().get_transactions().len());
+    assert!(block.get_block().get_timestamp() >=
+            genesis.get_block().get_timestamp());
+}
+
+#[test]
+fn test_block_builder_bounty() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0).set_bounty(&sk1, &pk1, 5);
+    let bounty_tx = tx_builder.build().unwrap();
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(bounty_tx.clone());
+    assert!(block_builder.build(&sk1).is_err());
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(bounty_tx).allow_bounty(true);
+    assert!(block_builder.build(&sk1).unwrap().verify().is_ok());
+}
+
+#[test]
+fn test_hashed_block_verify_timestamp() {
+    let (_, sk) = gen_keypair();
+    let now = now_utc().to_timespec().sec;
+    let mut block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build_at(&sk, now).unwrap();
+    assert!(block.verify_timestamp(now, 0).is_ok());
+    assert!(block.verify_timestamp(now - 100, 0).is_ok());
+    assert!(block.verify_timestamp(now + 1, 60).is_err());
+
+    block.mut_signed_block().mut_block().set_timestamp(now + 60 * 60);
+    assert!(block.verify_timestamp(now, 60).is_err());
+    assert!(block.verify_timestamp(now, 2 * 60 * 60).is_ok());
+}
+
+#[test]
+fn test_hashed_block_mine() {
+    let (_, sk) = gen_keypair();
+    let mut block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk).unwrap();
+    assert!(block.verify_difficulty().is_ok());
+
+    let block_hash = block.mine(8).unwrap();
+    assert!(block_hash.leading_zero_bits() >= 8);
+    assert_eq!(block_hash, block.decode_hash().unwrap());
+    assert!(block.verify_difficulty().is_ok());
+    assert!(block.verify().is_ok());
+
+    // Find a nonce which misses the target and check it is rejected.
+    let mut nonce = block.get_signed_block().get_nonce();
+    loop {
+        nonce += 1;
+        block.mut_signed_block().set_nonce(nonce);
+        if block.compute_hash().leading_zero_bits() < 8 { break; }
+    }
+    assert!(block.verify_hash().is_ok());
+    assert!(block.verify_difficulty().is_err());
+    assert!(block.verify().is_err());
+
+    // No nonce meets a difficulty of more bits than the hash has.
+    assert!(block.mine(8 * HASHBYTES as u32 + 1).is_err());
+}
+
+#[test]
+fn test_hashed_block_nonce_changes_hash() {
+    let (_, sk) = gen_keypair();
+    let mut block1 = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build_at(&sk, 1000).unwrap();
+    let mut block2 = block1.clone();
+    block1.mut_signed_block().set_nonce(1);
+    block2.mut_signed_block().set_nonce(2);
+    assert!(block1.verify_hash().is_err());
+
+    let hash1 = block1.compute_hash();
+    let hash2 = block2.compute_hash();
+    assert!(hash1 != hash2);
+    assert_eq!(hash1, block1.decode_hash().unwrap());
+    assert!(block1.verify_hash().is_ok());
+    assert!(block2.verify_hash().is_ok());
+    assert!(block1.get_signed_block().get_signature() ==
+            block2.get_signed_block().get_signature());
+    assert!(block1.verify().is_ok());
+    assert!(block2.verify().is_ok());
+}
+
+#[test]
+fn test_hashed_block_verify_error_kinds() {
+    let (_, sk) = gen_keypair();
+    let block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk).unwrap();
+
+    let mut tampered = block.clone();
+    tampered.mut_signed_block().mut_block().set_timestamp(0);
+    match tampered.verify() {
+        Err(ref err) if err.kind() == ErrorKind::HashMismatch => {},
+        result => panic!("expected a hash mismatch, got {:?}", result)
+    }
+
+    tampered.compute_hash();
+    assert_eq!(ErrorKind::SignatureInvalid,
+               tampered.verify().unwrap_err().kind());
+
+    let mut truncated = block.clone();
+    truncated.mut_hash().pop();
+    assert_eq!(ErrorKind::Decode, truncated.verify().unwrap_err().kind());
+}
+
+#[test]
+fn test_hashed_block_from_bytes_decode_error() {
+    use std::error::Error;
+
+    let malformed = [0xffu8, 0xff, 0xff, 0xff];
+    let pb_err = protobuf::parse_from_bytes::<HashedBlock>(&malformed)
+        .unwrap_err();
+    let mut versioned = BLOCK_MAGIC.to_vec();
+    versioned.push(BLOCK_FORMAT_VERSION);
+    versioned.push_all(&malformed);
+    let err = HashedBlock::from_bytes(&versioned).unwrap_err();
+    assert_eq!(ErrorKind::Decode, err.kind());
+    assert!(err.description().contains(pb_err.description()));
+}
+
+#[test]
+fn test_hashed_block_verify_op_sequence() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0);
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(tx_builder.build().unwrap());
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify().is_err());
+    assert!(block.verify_parallel().is_err());
+}
+
+#[test]
+fn test_hashed_block_verify_no_duplicate_txes() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let transaction = tx_builder.build().unwrap();
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(transaction.clone());
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify_no_duplicate_txes().is_ok());
+    assert!(block.verify().is_ok());
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder
+        .add_transaction(transaction.clone())
+        .add_transaction(transaction);
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify_no_duplicate_txes().is_err());
+    assert!(block.verify().is_err());
+    assert!(block.verify_parallel().is_err());
+}
+
+#[test]
+fn test_hashed_block_header() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 3);
+    for op_index in range(0, 10) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        block_builder.add_transaction(tx_builder.build().unwrap());
+    }
+    let mut block = block_builder.build(&sk1).unwrap();
+    block.mine(4).unwrap();
+
+    let header_bytes = block.header().to_bytes().unwrap();
+    assert!(header_bytes.len() < block.to_bytes().unwrap().len());
+    let header = BlockHeader::from_bytes(&header_bytes).unwrap();
+    assert_eq!(block.decode_hash().unwrap(), header.decode_hash().unwrap());
+    let signed_header = header.get_signed_header();
+    assert!(signed_header.verify_signature().is_ok());
+    assert_eq!(0, signed_header.get_block().get_transactions().len());
+    assert_eq!(3, signed_header.get_block().get_height());
+    assert_eq!(block.get_block().compute_merkle_root(),
+               signed_header.get_block().decode_merkle_root().unwrap());
+
+    // The transactions only enter the hash through the merkle root.
+    block.mut_signed_block().mut_block().mut_transactions().pop();
+    assert!(block.verify_hash().is_ok());
+    assert!(block.verify_merkle_root().is_err());
+    assert!(block.verify().is_err());
+
+    let mut tampered = block.header();
+    tampered.mut_signed_header().mut_block().set_timestamp(0);
+    assert!(tampered.verify_hash().is_err());
+    assert!(BlockHeader::from_bytes(&tampered.to_bytes().unwrap()).is_err());
+}
+
+#[test]
+fn test_genesis_builder_total_supply() {
+    use std::u64;
+
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), u64::MAX - 10);
+    builder.add_transfer(pk2.clone(), 10);
+    assert!(builder.build().is_ok());
+
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), u64::MAX - 10);
+    builder.add_transfer(pk2.clone(), 11);
+    assert!(builder.build().is_err());
+
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 10);
+    builder.add_transfer(pk2.clone(), 0);
+    assert!(builder.build().is_err());
+}
+
+#[test]
+fn test_hashed_block_summary() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(5), 7);
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        block_builder.add_transaction(tx_builder.build().unwrap());
+    }
+    let block = block_builder.build_at(&sk1, 1234).unwrap();
+    let summary = block.summary();
+    assert!(summary.contains(&block.decode_hash().unwrap().to_hex()));
+    assert!(summary.contains(&HashDigest::from_u64(5).to_hex()));
+    assert!(summary.contains("height: 7"));
+    assert!(summary.contains("transactions: 3"));
+    assert!(summary.contains("timestamp: 1234"));
+    assert!(HashedBlock::new().summary().contains("transactions: 0"));
+}
+
+#[test]
+fn test_signed_block_compute_hash_with() {
+    use crypto::ByteCountHash;
+
+    let (_, sk) = gen_keypair();
+    let mut block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk).unwrap();
+    block.mine(1).unwrap();
+    let header_bytes =
+        block.get_signed_block().header().write_to_bytes().unwrap();
+    assert_eq!(HashDigest::from_u64(header_bytes.len() as u64),
+               block.get_signed_block().compute_hash_with::<ByteCountHash>());
+    assert_eq!(hash(&header_bytes),
+               block.get_signed_block().compute_hash_with::<Sha512>());
+    assert_eq!(hash(&header_bytes), block.compute_hash());
+}
+
+#[test]
+fn test_hashed_block_verified_transactions() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        block_builder.add_transaction(tx_builder.build().unwrap());
+    }
+    let mut block = block_builder.build(&sk1).unwrap();
+    {
+        let verified = block.verified_transactions().unwrap();
+        assert_eq!(3, verified.len());
+        for (tx, block_tx) in verified.iter()
+            .zip(block.get_block().get_transactions().iter())
+        {
+            assert_eq!(*tx, block_tx);
+        }
+    }
+
+    block.mut_signed_block().mut_block().mut_transactions()[1]
+        .mut_signatures()[0].mut_payload()[0] ^= 1;
+    let err = block.verified_transactions().unwrap_err();
+    assert_eq!(ErrorKind::SignatureInvalid, err.kind());
+    assert!(format!("{}", err).contains("Transaction 1 "));
+}
+
+#[test]
+fn test_hashed_block_verify_with_stats() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk1).unwrap();
+    assert_eq!(VerifyStats { transactions_checked: 0, signatures_checked: 1 },
+               block.verify_with_stats().unwrap());
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    block_builder.add_transaction(tx_builder.build().unwrap());
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 10, 1)
+        .add_transfer(&sk2, &pk2, &pk1, 5, 0);
+    block_builder.add_transaction(tx_builder.build().unwrap());
+    let mut block = block_builder.build(&sk1).unwrap();
+    assert_eq!(VerifyStats { transactions_checked: 2, signatures_checked: 4 },
+               block.verify_with_stats().unwrap());
+
+    block.mut_signed_block().mut_block().mut_transactions()[1]
+        .mut_signatures()[1].mut_payload()[0] ^= 1;
+    assert!(block.verify_with_stats().is_err());
+}
+
+#[test]
+fn test_genesis_builder_chained() {
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder
+        .add_transfer(pk1.clone(), 100)
+        .add_transfer(pk2.clone(), 200)
+        .add_transfer(pk1.clone(), 300);
+    let chained = builder.build_deterministic(b"seed", 1000).unwrap();
+    let transfers = chained.get_block().get_transactions()[0]
+        .get_commit().get_transfers();
+    assert_eq!(3, transfers.len());
+    assert_eq!(pk2.as_slice(), transfers[1].get_destination_pk());
+    assert_eq!(300, transfers[2].get_tokens());
+
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    builder.add_transfer(pk2, 200);
+    builder.add_transfer(pk1, 300);
+    assert!(chained == builder.build_deterministic(b"seed", 1000).unwrap());
+}
+
+#[test]
+fn test_hashed_block_timestamp() {
+    let mut block = HashedBlock::new();
+    assert_eq!(Timespec::new(0, 0), block.timestamp());
+
+    block.set_timestamp(Timespec::new(1420070400, 0));
+    assert_eq!(1420070400, block.get_block().get_timestamp());
+    assert_eq!(Timespec::new(1420070400, 0), block.timestamp());
+
+    block.set_timestamp(Timespec::new(1420070401, 999999999));
+    assert_eq!(Timespec::new(1420070401, 0), block.timestamp());
+
+    let genesis = GenesisBuilder::new().build_deterministic(b"seed", 1234)
+        .unwrap();
+    assert_eq!(1234, genesis.timestamp().sec);
+}
+
+#[test]
+fn test_hashed_block_verify_is_genesis() {
+    let (pk1, sk1) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    assert!(genesis.verify_is_genesis().is_ok());
+    assert!(GenesisBuilder::new().build().unwrap().verify_is_genesis().is_ok());
+
+    let mut with_previous = genesis.clone();
+    with_previous.set_previous_block(&HashDigest::from_u64(1));
+    assert!(with_previous.verify_is_genesis().is_err());
+
+    let block = BlockBuilder::new(genesis.decode_hash().unwrap(), 1)
+        .build(&sk1).unwrap();
+    assert!(block.verify().is_ok());
+    assert!(block.verify_is_genesis().is_err());
+
+    let mut with_bounty = genesis.clone();
+    with_bounty.mut_signed_block().mut_block().mut_transactions()[0]
+        .mut_commit().set_bounty(1);
+    assert!(with_bounty.verify_is_genesis().is_err());
+}
+
+#[test]
+fn test_hashed_block_verify_bounty() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_bounty(&sk1, &pk2, &pk1, 20, 0);
+    let bounty_tx = tx_builder.build().unwrap();
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(bounty_tx).allow_bounty(true);
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify().is_ok());
+    assert!(block.verify_parallel().is_ok());
+
+    // The bounty is claimed for a key which doesn't pay it out.
+    let (pk3, sk3) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 20, 1).set_bounty(&sk3, &pk3, 20);
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(tx_builder.build().unwrap())
+        .allow_bounty(true);
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify().is_err());
+    assert!(block.verify_parallel().is_err());
+}
+
+#[test]
+fn test_genesis_builder_multiple_minting_keys() {
+    let (founder1_pk, founder1_sk) = gen_keypair();
+    let (founder2_pk, founder2_sk) = gen_keypair();
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder
+        .add_transfer_from(founder1_sk.clone(), founder1_pk.clone(),
+                           pk1.clone(), 100, 0)
+        .add_transfer_from(founder2_sk.clone(), founder2_pk.clone(),
+                           pk2.clone(), 200, 0)
+        .add_transfer(pk1.clone(), 5)
+        .add_transfer_from(founder1_sk, founder1_pk.clone(), pk2.clone(),
+                           300, 1);
+    let genesis = builder.build().unwrap();
+    assert!(genesis.verify().is_ok());
+    assert!(genesis.verify_is_genesis().is_ok());
+
+    let genesis_tx = &genesis.get_block().get_transactions()[0];
+    assert!(genesis_tx.verify_signatures().is_ok());
+    assert_eq!(4, genesis_tx.get_signatures().len());
+    let sources: Vec<&[u8]> = genesis_tx.get_commit().get_transfers().iter()
+        .map(|transfer| transfer.get_source_pk()).collect();
+    assert_eq!(founder1_pk.as_slice(), sources[0]);
+    assert_eq!(founder2_pk.as_slice(), sources[1]);
+    assert_eq!(founder1_pk.as_slice(), sources[3]);
+    assert!(sources[2] != founder1_pk.as_slice());
+
+    // With the wrong secret key for its source the genesis can't be signed.
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer_from(founder2_sk, founder1_pk, pk1, 100, 0);
+    assert!(builder.build().is_err());
+}
+
+#[test]
+fn test_hashed_block_bytes_magic_and_version() {
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let bytes = genesis.to_bytes().unwrap();
+    assert_eq!(BLOCK_MAGIC, &bytes[..BLOCK_MAGIC.len()]);
+    assert_eq!(BLOCK_FORMAT_VERSION, bytes[BLOCK_MAGIC.len()]);
+    assert!(genesis == HashedBlock::from_bytes(&bytes).unwrap());
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[0] ^= 0xff;
+    assert_eq!(ErrorKind::Decode,
+               HashedBlock::from_bytes(&wrong_magic).unwrap_err().kind());
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[BLOCK_MAGIC.len()] = BLOCK_FORMAT_VERSION + 1;
+    let err = HashedBlock::from_bytes(&wrong_version).unwrap_err();
+    assert!(format!("{}", err).contains("version"));
+
+    let unversioned = genesis.write_to_bytes().unwrap();
+    assert!(HashedBlock::from_bytes(&unversioned).is_err());
+    assert!(HashedBlock::from_bytes(BLOCK_MAGIC).is_err());
+    assert!(HashedBlock::from_bytes(&[]).is_err());
+}
+
+#[test]
+fn test_hashed_block_verify_genesis() {
+    let (pk1, sk1) = gen_keypair();
+    let (staker_pk, staker_sk) = gen_keypair();
+    let mut builder = GenesisBuilder::with_keypair(staker_pk, staker_sk.clone());
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    assert!(genesis.verify_genesis().is_ok());
+
+    let mut tampered = genesis.clone();
+    tampered.mut_signed_block().mut_block().set_height(1);
+    assert!(tampered.verify_genesis().is_err());
+
+    // A bounty transaction sneaked in and resealed passes verify(), but not
+    // the genesis rules.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_bounty(&sk1, &staker_sk.public_key(), &pk1, 10, 0);
+    let mut with_bounty = genesis.clone();
+    with_bounty.mut_signed_block().mut_block().mut_transactions()
+        .push(tx_builder.build().unwrap());
+    with_bounty.reseal(&staker_sk);
+    assert!(with_bounty.verify().is_ok());
+    assert!(with_bounty.verify_genesis().is_err());
+}
+
+#[test]
+fn test_hashed_blocks_keyed_by_hash() {
+    use std::collections::BTreeMap;
+
+    let (_, sk) = gen_keypair();
+    let mut blocks_by_hash = BTreeMap::new();
+    let mut blocks = vec![];
+    for height in range(0, 5) {
+        let block = BlockBuilder::new(HashDigest::from_u64(1), height)
+            .build(&sk).unwrap();
+        blocks_by_hash.insert(block.decode_hash().unwrap(), block.clone());
+        blocks.push(block);
+    }
+    assert_eq!(5, blocks_by_hash.len());
+    for block in blocks.iter() {
+        assert!(blocks_by_hash.get(&block.decode_hash().unwrap()) == Some(block));
+    }
+    assert!(blocks_by_hash.get(&HashDigest::from_u64(1)).is_none());
+
+    // Iteration follows the ordering of HashDigest.
+    let hashes: Vec<&HashDigest> = blocks_by_hash.keys().collect();
+    for index in range(1, hashes.len()) {
+        assert!(hashes[index - 1] < hashes[index]);
+    }
+    blocks.sort_by(|left, right| {
+        left.decode_hash().unwrap().cmp(&right.decode_hash().unwrap())
+    });
+    assert!(blocks.iter().zip(blocks_by_hash.values()).all(
+        |(sorted, stored)| sorted == stored));
+}
+
+#[test]
+fn test_hashed_block_verify_size() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        block_builder.add_transaction(tx_builder.build().unwrap());
+    }
+    let block = block_builder.build(&sk1).unwrap();
+    let size = block.write_to_bytes().unwrap().len();
+    assert!(block.verify_size(size).is_ok());
+    assert!(block.verify_size(size + 1).is_ok());
+    assert!(block.verify_size(size - 1).is_err());
+    assert!(block.verify_size(0).is_err());
+}
+
+#[test]
+fn test_hashed_block_verify_transaction_sizes() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut small_builder = TransactionBuilder::new();
+    small_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let small = small_builder.build().unwrap();
+    let mut large_builder = TransactionBuilder::new();
+    large_builder
+        .add_transfer(&sk1, &pk1, &pk2, 10, 1)
+        .add_transfer(&sk2, &pk2, &pk1, 5, 0);
+    let large = large_builder.build().unwrap();
+    let large_size = large.write_to_bytes().unwrap().len();
+    assert!(small.write_to_bytes().unwrap().len() < large_size);
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transactions(vec![small, large.clone()]);
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify_transaction_sizes(large_size + 1).is_ok());
+    assert!(block.verify_transaction_sizes(large_size).is_ok());
+    let err = block.verify_transaction_sizes(large_size - 1).unwrap_err();
+    assert!(format!("{}", err).contains(&format!(
+        "Transaction 1 ({})", large.compute_id())));
+}
+
+#[test]
+fn test_block_builder_sort_transactions() {
+    let (pk, sk) = gen_keypair();
+    let mut transactions = vec![];
+    for _ in range(0, 6) {
+        let (source_pk, source_sk) = gen_keypair();
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&source_sk, &source_pk, &pk, 10, 0);
+        transactions.push(tx_builder.build().unwrap());
+    }
+    let build_with = |transactions: &[Transaction], sort: bool| {
+        let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+        for tx in transactions.iter() {
+            block_builder.add_transaction(tx.clone());
+        }
+        if sort { block_builder.sort_transactions(); }
+        block_builder.build_at(&sk, 1000).unwrap()
+    };
+    let mut shuffled = transactions.clone();
+    shuffled.reverse();
+    shuffled.swap(1, 4);
+    shuffled.swap(0, 2);
+
+    let sorted = build_with(&transactions, true);
+    assert_eq!(sorted.decode_hash().unwrap(),
+               build_with(&shuffled, true).decode_hash().unwrap());
+    assert!(build_with(&transactions, false).decode_hash().unwrap() !=
+            build_with(&shuffled, false).decode_hash().unwrap());
+    assert!(sorted.verify().is_ok());
+    let ids: Vec<HashDigest> = sorted.get_block().get_transactions().iter()
+        .map(|tx| tx.compute_id()).collect();
+    for index in range(1, ids.len()) {
+        assert!(ids[index - 1] < ids[index]);
+    }
+}
+
+#[test]
+fn test_hashed_block_verify_names_failing_transaction() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut transactions = vec![];
+    for op_index in range(0, 4) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        transactions.push(tx_builder.build().unwrap());
+    }
+    transactions[2].mut_signatures()[0].mut_payload()[0] ^= 1;
+    let bad_id = transactions[2].compute_id();
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    for tx in transactions.into_iter() {
+        block_builder.add_transaction(tx);
+    }
+    let block = block_builder.build(&sk1).unwrap();
+    let message = format!("{}", block.verify().unwrap_err());
+    assert!(message.contains("Transaction 2 "));
+    assert!(message.contains(&bad_id.to_hex()));
+}
+
+#[test]
+fn test_hashed_block_verify_hash_matches_decoding() {
+    // What verify_hash did before it worked on the raw bytes.
+    fn decoding_verify_hash(block: &HashedBlock) -> IroncResult<()> {
+        let block_hash = try!(HashDigest::from_slice(&block.get_hash()));
+        try!(block.decode_previous());
+        let computed_hash = block.get_signed_block().compute_hash();
+        if computed_hash.ct_eq(&block_hash) { Ok(()) }
+        else { Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+            "Block has invalid hash: {} != {} (actual)",
+            block_hash, computed_hash)))
+        }
+    }
+
+    let (pk1, sk1) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut blocks = vec![genesis.clone()];
+    blocks.push(BlockBuilder::new(genesis.decode_hash().unwrap(), 1)
+                .build(&sk1).unwrap());
+    let mut mined = blocks[1].clone();
+    mined.mine(3).unwrap();
+    blocks.push(mined);
+
+    let mut wrong_hash = genesis.clone();
+    wrong_hash.mut_hash()[0] ^= 1;
+    blocks.push(wrong_hash);
+    let mut short_hash = genesis.clone();
+    short_hash.mut_hash().pop();
+    blocks.push(short_hash);
+    let mut no_previous = genesis.clone();
+    no_previous.mut_signed_block().mut_block().clear_previous();
+    blocks.push(no_previous);
+    let mut tampered = genesis.clone();
+    tampered.mut_signed_block().mut_block().set_timestamp(0);
+    blocks.push(tampered);
+    blocks.push(HashedBlock::new());
+
+    for block in blocks.iter() {
+        let expected = decoding_verify_hash(block);
+        let actual = block.verify_hash();
+        assert_eq!(expected.is_ok(), actual.is_ok());
+        if let (Err(expected), Err(actual)) = (expected, actual) {
+            assert_eq!(expected.kind(), actual.kind());
+            assert_eq!(format!("{}", expected), format!("{}", actual));
+        }
+    }
+}
+
+#[test]
+fn test_block_builder_add_transactions_moves() {
+    let (pk, sk) = gen_keypair();
+    let mut transactions = vec![];
+    for op_index in range(0, 5) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk, &pk, &pk, 10, op_index);
+        transactions.push(tx_builder.into_transaction().unwrap());
+    }
+    let tx_ids: Vec<HashDigest> =
+        transactions.iter().map(|tx| tx.compute_id()).collect();
+    let buffer = transactions.as_ptr();
+
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transactions(transactions);
+    let block = block_builder.build(&sk).unwrap();
+    // The block holds the very vector which was passed in.
+    assert_eq!(buffer, block.get_block().get_transactions().as_ptr());
+    let block_ids: Vec<HashDigest> = block.get_block().get_transactions()
+        .iter().map(|tx| tx.compute_id()).collect();
+    assert_eq!(tx_ids, block_ids);
+    assert!(block.verify().is_ok());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk, &pk, &pk, 10, 5);
+    let last = tx_builder.build().unwrap();
+    assert_eq!(last.compute_id(),
+               tx_builder.into_transaction().unwrap().compute_id());
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder
+        .add_transaction(last.clone())
+        .add_transactions(block.get_block().get_transactions().to_vec());
+    let block = block_builder.build(&sk).unwrap();
+    assert_eq!(6, block.get_block().get_transactions().len());
+    assert_eq!(last.compute_id(),
+               block.get_block().get_transactions()[0].compute_id());
+}
+
+#[test]
+fn test_hashed_block_reseal() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut genesis_builder = GenesisBuilder::new();
+    genesis_builder.add_transfer(pk1.clone(), 100);
+    let genesis = genesis_builder.build().unwrap();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let mut block_builder =
+        BlockBuilder::new(genesis.decode_hash().unwrap(), 1);
+    block_builder.add_transaction(tx_builder.build().unwrap());
+    let mut block = block_builder.build(&sk1).unwrap();
+    let original_hash = block.decode_hash().unwrap();
+
+    block.mut_signed_block().mut_block().mut_transactions()[0]
+        .mut_commit().mut_transfers()[0].set_fee(1);
+    assert!(block.verify_merkle_root().is_err());
+    block.mut_signed_block().mut_block().set_timestamp(0);
+    assert!(block.verify_hash().is_err());
+    assert!(block.get_signed_block().verify_signature().is_err());
+
+    let (_, staker_sk) = gen_keypair();
+    let resealed_hash = block.reseal(&staker_sk);
+    assert_eq!(resealed_hash, block.decode_hash().unwrap());
+    assert!(resealed_hash != original_hash);
+    assert!(block.verify_hash().is_ok());
+    assert!(block.verify_merkle_root().is_ok());
+    assert!(block.get_signed_block().verify_signature().is_ok());
+    assert_eq!(staker_sk.public_key().as_slice(),
+               block.get_block().get_staker_pk());
+}
+
+#[test]
+fn test_hashed_block_check_seal_invariants() {
+    let (pk, sk) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk, &pk, &pk, 10, 0);
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transaction(tx_builder.build().unwrap());
+    let block = block_builder.build(&sk).unwrap();
+    assert!(block.check_seal_invariants().is_ok());
+    assert!(GenesisBuilder::new().build().unwrap()
+            .check_seal_invariants().is_ok());
+
+    // Hashed, then signed: the stored hash misses the new signature.
+    let (_, other_sk) = gen_keypair();
+    let mut wrong_order = block.clone();
+    wrong_order.mut_signed_block().mut_block().set_timestamp(0);
+    wrong_order.compute_hash();
+    wrong_order.mut_signed_block().sign(&other_sk);
+    assert!(wrong_order.get_signed_block().verify_signature().is_ok());
+    assert_eq!(ErrorKind::HashMismatch,
+               wrong_order.check_seal_invariants().unwrap_err().kind());
+    wrong_order.reseal(&other_sk);
+    assert!(wrong_order.check_seal_invariants().is_ok());
+
+    let mut unsigned = block.clone();
+    unsigned.mut_signed_block().mut_block().set_timestamp(0);
+    unsigned.compute_hash();
+    assert!(unsigned.check_seal_invariants().is_err());
+
+    let mut changed_txs = block.clone();
+    changed_txs.mut_signed_block().mut_block().mut_transactions().clear();
+    assert!(changed_txs.check_seal_invariants().is_err());
+}
+
+#[test]
+fn test_block_coinbase() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (miner_pk, _) = gen_keypair();
+    let previous = HashDigest::from_u64(1);
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 10, 3, 0);
+    let transaction = tx_builder.build().unwrap();
+
+    let mut block_builder = BlockBuilder::new(previous.clone(), 1);
+    block_builder
+        .add_transaction(transaction.clone())
+        .set_coinbase(miner_pk.clone());
+    let block = block_builder.build(&sk1).unwrap();
+    {
+        let coinbase = block.coinbase().unwrap();
+        assert!(coinbase.is_coinbase());
+        let transfer = &coinbase.get_commit().get_transfers()[0];
+        assert_eq!(miner_pk.as_slice(), transfer.get_destination_pk());
+        assert_eq!(BLOCK_REWARD + 3, transfer.get_tokens());
+    }
+    assert!(block.verify().is_ok());
+    assert!(block.verify_parallel().is_ok());
+    assert!(block.verify_coinbase(BLOCK_REWARD).is_ok());
+    assert!(block.verify_coinbase(BLOCK_REWARD + 1).is_err());
+    assert_eq!(2, block.verify_with_stats().unwrap().signatures_checked);
+
+    // verify() accepts a block without a coinbase, verify_coinbase() doesn't.
+    let mut block_builder = BlockBuilder::new(previous.clone(), 1);
+    block_builder.add_transaction(transaction.clone());
+    let without = block_builder.build(&sk1).unwrap();
+    assert!(without.coinbase().is_none());
+    assert!(without.verify().is_ok());
+    assert_eq!(ErrorKind::Conservation,
+               without.verify_coinbase(BLOCK_REWARD).unwrap_err().kind());
+
+    let build_with = |transactions: Vec<Transaction>| {
+        let mut block_builder = BlockBuilder::new(previous.clone(), 1);
+        block_builder.add_transactions(transactions);
+        block_builder.build(&sk1).unwrap()
+    };
+    let exact = build_with(vec![
+        transaction.clone(), make_coinbase(&miner_pk, BLOCK_REWARD + 3, 1)]);
+    assert!(exact.verify().is_ok());
+    let over_reward = build_with(vec![
+        transaction.clone(), make_coinbase(&miner_pk, BLOCK_REWARD + 4, 1)]);
+    assert_eq!(ErrorKind::Conservation,
+               over_reward.verify().unwrap_err().kind());
+    assert!(over_reward.verify_parallel().is_err());
+    let under_reward = build_with(vec![
+        transaction.clone(), make_coinbase(&miner_pk, BLOCK_REWARD, 1)]);
+    assert!(under_reward.verify().is_err());
+
+    let two_coinbases = build_with(vec![
+        make_coinbase(&miner_pk, BLOCK_REWARD, 1),
+        make_coinbase(&pk2, BLOCK_REWARD, 1)]);
+    assert_eq!(ErrorKind::Conservation,
+               two_coinbases.verify().unwrap_err().kind());
+    assert!(two_coinbases.verify_coinbase(BLOCK_REWARD).is_err());
+
+    let wrong_height =
+        build_with(vec![make_coinbase(&miner_pk, BLOCK_REWARD, 0)]);
+    assert!(wrong_height.verify().is_err());
+    let mut with_source = make_coinbase(&miner_pk, BLOCK_REWARD, 1);
+    with_source.mut_commit().mut_transfers()[0].set_source_pk(pk1.0.to_vec());
+    assert!(build_with(vec![with_source]).verify().is_err());
+
+    // A coinbase is never valid on its own, e.g. when relayed.
+    assert!(make_coinbase(&miner_pk, 1, 1).verify_signatures().is_err());
+}
+
+#[test]
+fn test_hashed_block_rechain() {
+    let (pk, sk) = gen_keypair();
+    let mut genesis_builder = GenesisBuilder::new();
+    genesis_builder.add_transfer(pk.clone(), 100);
+    let genesis = genesis_builder.build().unwrap();
+    let mut blocks = vec![genesis];
+    for height in range(1, 3) {
+        let mut block = HashedBlock::new();
+        block.mut_signed_block().mut_block().set_height(height);
+        block.mut_signed_block().mut_block().set_timestamp(
+            blocks[0].get_block().get_timestamp());
+        let previous = blocks.last().unwrap().decode_hash().unwrap();
+        let block_hash = block.rechain(&previous, &sk);
+        assert_eq!(block_hash, block.decode_hash().unwrap());
+        assert_eq!(previous, block.decode_previous().unwrap());
+        assert!(block.verify().is_ok());
+        blocks.push(block);
+    }
+    assert!(verify_chain(&blocks).is_ok());
+
+    // Rechaining an existing block moves it without invalidating it.
+    let mut moved = blocks[2].clone();
+    moved.rechain(&HashDigest::from_u64(7), &sk);
+    assert!(moved.verify().is_ok());
+    assert!(moved.decode_hash().unwrap() != blocks[2].decode_hash().unwrap());
+    assert!(verify_chain(&[blocks[0].clone(), blocks[1].clone(), moved])
+            .is_err());
+}
+
+#[test]
+fn test_builders_with_clock() {
+    struct FixedClock(i64);
+    impl Clock for FixedClock {
+        fn now(&self) -> Timespec { Timespec::new(self.0, 500) }
+    }
+
+    let (pk, sk) = gen_keypair();
+    let clock = FixedClock(1234567);
+    let build_genesis = || {
+        let mut builder = GenesisBuilder::with_keypair(pk.clone(), sk.clone());
+        builder.add_transfer(pk.clone(), 100);
+        builder.build_with_clock(&clock).unwrap()
+    };
+    let genesis = build_genesis();
+    assert_eq!(1234567, genesis.get_block().get_timestamp());
+    assert_eq!(Timespec::new(1234567, 0), genesis.timestamp());
+    assert_eq!(genesis.decode_hash().unwrap(),
+               build_genesis().decode_hash().unwrap());
+    assert!(genesis.verify_genesis().is_ok());
+
+    let genesis_hash = genesis.decode_hash().unwrap();
+    let build_block = || {
+        BlockBuilder::new(genesis_hash.clone(), 1)
+            .build_with_clock(&sk, &clock).unwrap()
+    };
+    let block = build_block();
+    assert_eq!(1234567, block.get_block().get_timestamp());
+    assert_eq!(block.decode_hash().unwrap(),
+               build_block().decode_hash().unwrap());
+    assert_eq!(block.decode_hash().unwrap(),
+               BlockBuilder::new(genesis_hash.clone(), 1)
+               .build_at(&sk, 1234567).unwrap().decode_hash().unwrap());
+    assert!(block.verify().is_ok());
+
+    let later = BlockBuilder::new(genesis_hash.clone(), 1)
+        .build_with_clock(&sk, &FixedClock(1234568)).unwrap();
+    assert!(later.decode_hash().unwrap() != block.decode_hash().unwrap());
+}
+
+#[test]
+fn test_hashed_block_tx_bloom() {
+    let (pk, sk) = gen_keypair();
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    let mut tx_ids = vec![];
+    for op_index in range(0, 20) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk, &pk, &pk, 10, op_index);
+        let transaction = tx_builder.build().unwrap();
+        tx_ids.push(transaction.compute_id());
+        block_builder.add_transaction(transaction);
+    }
+    let block = block_builder.build(&sk).unwrap();
+    let filter = block.tx_bloom();
+    assert!(tx_ids.iter().all(|tx_id| filter.might_contain(tx_id)));
+
+    // False positives are allowed, but should be rare.
+    let absent = range(0, 200u64).filter(|&value| {
+        !filter.might_contain(&hash(&HashDigest::from_u64(value).0))
+    }).count();
+    assert!(absent > 180);
+    assert!(!HashedBlock::new().tx_bloom().might_contain(&tx_ids[0]));
+}
+
+#[test]
+fn test_hashed_block_verify_transaction_inclusion() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut transactions = vec![];
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        transactions.push(tx_builder.build().unwrap());
+    }
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder
+        .add_transactions(transactions[..2].to_vec())
+        .set_coinbase(pk1.clone());
+    let block = block_builder.build(&sk1).unwrap();
+    for tx in block.get_block().get_transactions().iter() {
+        assert!(block.verify_transaction_inclusion(tx).unwrap());
+    }
+    assert!(!block.verify_transaction_inclusion(&transactions[2]).unwrap());
+
+    // A transaction which doesn't match the one in the block isn't in it.
+    let mut tampered = transactions[1].clone();
+    tampered.mut_commit().mut_transfers()[0].set_tokens(11);
+    assert!(!block.verify_transaction_inclusion(&tampered).unwrap());
+
+    // A tampered transaction in a block which was sealed with it.
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transactions(
+        vec![transactions[0].clone(), tampered.clone()]);
+    let sealed_tampered = block_builder.build(&sk1).unwrap();
+    assert!(sealed_tampered.verify_transaction_inclusion(&transactions[0])
+            .unwrap());
+    assert_eq!(ErrorKind::SignatureInvalid,
+               sealed_tampered.verify_transaction_inclusion(&tampered)
+               .unwrap_err().kind());
+
+    // The merkle root doesn't commit to a transaction swapped in later.
+    let mut swapped = block.clone();
+    swapped.mut_signed_block().mut_block().mut_transactions()[1] =
+        tampered.clone();
+    assert_eq!(ErrorKind::HashMismatch,
+               swapped.verify_transaction_inclusion(&tampered)
+               .unwrap_err().kind());
+
+    // Nor to other signatures over the same commitment, which share its id.
+    let mut resigned = transactions[1].clone();
+    resigned.mut_signatures()[0].mut_payload()[0] ^= 1;
+    assert_eq!(transactions[1].compute_id(), resigned.compute_id());
+    assert_eq!(ErrorKind::HashMismatch,
+               block.verify_transaction_inclusion(&resigned)
+               .unwrap_err().kind());
+}
+
+#[test]
+fn test_genesis_distribution() {
+    let (pk1, _) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let (founder_pk, founder_sk) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder
+        .add_transfer(pk1.clone(), 100)
+        .add_transfer_from(founder_sk, founder_pk, pk2.clone(), 200, 0)
+        .add_transfer(pk3.clone(), 300);
+    let genesis = builder.build().unwrap();
+    assert_eq!(vec![(pk1.clone(), 100), (pk2, 200), (pk3, 300)],
+               genesis.distribution().unwrap());
+    assert_eq!(0, GenesisBuilder::new().build().unwrap()
+               .distribution().unwrap().len());
+
+    let (_, sk) = gen_keypair();
+    let block = BlockBuilder::new(genesis.decode_hash().unwrap(), 1)
+        .build(&sk).unwrap();
+    assert!(block.distribution().is_err());
+}
+
+#[test]
+fn test_signed_block_sign_checked() {
+    let (pk, sk) = gen_keypair();
+    let mut block = HashedBlock::new();
+    block.set_previous_block(&HashDigest::from_u64(1));
+    block.mut_signed_block().mut_block().set_height(1);
+    assert!(block.mut_signed_block().sign_checked(&sk).is_ok());
+    assert_eq!(pk.as_slice(), block.get_block().get_staker_pk());
+    block.compute_hash();
+    assert!(block.verify().is_ok());
+
+    // The secret half of one key with the public half of another.
+    let (other_pk, _) = gen_keypair();
+    let mut mismatched = sk.clone();
+    let public_start = mismatched.0.len() - other_pk.0.len();
+    for (byte, &other) in mismatched.0[public_start..].iter_mut()
+        .zip(other_pk.0.iter())
+    {
+        *byte = other;
+    }
+    let err = block.mut_signed_block().sign_checked(&mismatched).unwrap_err();
+    assert_eq!(ErrorKind::SignatureInvalid, err.kind());
+}
+
+#[test]
+fn test_verify_block_free_functions() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 10, 2, 0);
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder
+        .add_transaction(tx_builder.build().unwrap())
+        .set_coinbase(pk1.clone());
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(verify_block_header(&block).is_ok());
+    assert_eq!(block.verify_with_stats().unwrap(),
+               verify_block(&block, BLOCK_REWARD).unwrap());
+    assert_eq!(VerifyStats { transactions_checked: 2, signatures_checked: 2 },
+               verify_block(&block, BLOCK_REWARD).unwrap());
+    // The coinbase pays BLOCK_REWARD, more than a smaller reward allows.
+    assert_eq!(ErrorKind::Conservation,
+               verify_block(&block, BLOCK_REWARD - 1).unwrap_err().kind());
+
+    // Both fail the same way as the methods.
+    let mut bad_tx = block.clone();
+    bad_tx.mut_signed_block().mut_block().mut_transactions()[1]
+        .mut_signatures()[0].mut_payload()[0] ^= 1;
+    bad_tx.reseal(&sk1);
+    assert!(verify_block_header(&bad_tx).is_ok());
+    assert_eq!(format!("{}", bad_tx.verify().unwrap_err()),
+               format!("{}", verify_block(&bad_tx, BLOCK_REWARD).unwrap_err()));
+
+    let mut bad_sig = block.clone();
+    let mut signature = bad_sig.get_signed_block().get_signature().to_vec();
+    signature[0] ^= 0xff;
+    bad_sig.mut_signed_block().set_signature(signature);
+    bad_sig.compute_hash();
+    assert_eq!(bad_sig.verify_header().unwrap_err().kind(),
+               verify_block_header(&bad_sig).unwrap_err().kind());
+    assert_eq!(ErrorKind::SignatureInvalid,
+               verify_block(&bad_sig, BLOCK_REWARD).unwrap_err().kind());
+}
+
+#[test]
+fn test_block_signature_scheme() {
+    let (_, sk) = gen_keypair();
+    let mut block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk).unwrap();
+    assert!(!block.get_block().has_signature_scheme());
+    assert_eq!(SignatureScheme::Ed25519, block.get_block().signature_scheme());
+    let unset_hash = block.decode_hash().unwrap();
+
+    // Recording the scheme explicitly is covered by the signature and hash.
+    block.mut_signed_block().mut_block()
+        .set_signature_scheme(Block_SignatureScheme::ED25519);
+    assert!(block.get_block().header().has_signature_scheme());
+    assert!(block.get_signed_block().verify_signature().is_err());
+    let recorded_hash = block.reseal(&sk);
+    assert!(recorded_hash != unset_hash);
+    assert_eq!(SignatureScheme::Ed25519, block.get_block().signature_scheme());
+    assert!(block.verify().is_ok());
+}
+
+#[test]
+fn test_hashed_block_would_be_hash() {
+    let (_, sk) = gen_keypair();
+    let mut block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk).unwrap();
+    let block_hash = block.decode_hash().unwrap();
+    assert_eq!(block_hash, block.would_be_hash());
+
+    block.mut_signed_block().mut_block().set_height(2);
+    block.mut_signed_block().sign(&sk);
+    let new_hash = block.would_be_hash();
+    assert!(new_hash != block_hash);
+    assert_eq!(block_hash, block.decode_hash().unwrap());
+    assert!(block.verify_hash().is_err());
+
+    assert_eq!(new_hash, block.compute_hash());
+    assert_eq!(new_hash, block.decode_hash().unwrap());
+    assert!(block.verify_hash().is_ok());
+}
+
+#[test]
+fn test_hashed_block_verify_merkle_root_duplicate_pair() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut transactions = vec![];
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        transactions.push(tx_builder.build().unwrap());
+    }
+    let mut block_builder = BlockBuilder::new(HashDigest::from_u64(1), 1);
+    block_builder.add_transactions(transactions.clone());
+    let block = block_builder.build(&sk1).unwrap();
+    assert!(block.verify_merkle_root().is_ok());
+
+    // Repeating the last transaction keeps the root, and so the block hash.
+    let mut duplicated = block.clone();
+    duplicated.mut_signed_block().mut_block().mut_transactions()
+        .push(transactions[2].clone());
+    assert_eq!(block.get_block().compute_merkle_root(),
+               duplicated.get_block().compute_merkle_root());
+    assert!(duplicated.verify_hash().is_ok());
+    assert_eq!(ErrorKind::HashMismatch,
+               duplicated.verify_merkle_root().unwrap_err().kind());
+    assert!(duplicated.verify().is_err());
+}
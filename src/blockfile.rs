@@ -0,0 +1,195 @@
+use std::error::FromError;
+use std::io::{self, Read, Write};
+
+use block::HashedBlockExt;
+use codec::{MAX_VARINT_BYTES, decode_varint, encode_varint};
+use error::{ErrorKind, IroncError, IroncResult};
+use ironcoin_pb::HashedBlock;
+
+// A block file is a sequence of records, each a varint length followed by
+// that many bytes of HashedBlockExt::to_bytes(), e.g. a dump of a chain.
+pub struct BlockFileReader<R: Read> {
+    reader: R,
+    done: bool
+}
+
+impl<R: Read> BlockFileReader<R> {
+    pub fn new(reader: R) -> BlockFileReader<R> {
+        BlockFileReader { reader: reader, done: false }
+    }
+
+    // None at the end of the file, which only happens between records.
+    fn read_record_length(&mut self) -> IroncResult<Option<u64>> {
+        let mut prefix = vec![];
+        while prefix.len() < MAX_VARINT_BYTES {
+            match try!(read_byte(&mut self.reader)) {
+                Some(byte) => {
+                    prefix.push(byte);
+                    if byte & 0x80 == 0 { break; }
+                },
+                None if prefix.len() == 0 => return Ok(None),
+                None => return Err(truncated("record length"))
+            }
+        }
+        let (length, _) = try!(decode_varint(&prefix));
+        Ok(Some(length))
+    }
+
+    fn read_block(&mut self) -> IroncResult<Option<HashedBlock>> {
+        let length = match try!(self.read_record_length()) {
+            Some(length) => length,
+            None => return Ok(None)
+        };
+        // Read through take() so a corrupt length can't make us allocate it.
+        let mut bytes = vec![];
+        try!(self.reader.by_ref().take(length).read_to_end(&mut bytes));
+        if (bytes.len() as u64) < length {
+            return Err(truncated("block"));
+        }
+        HashedBlock::from_bytes(&bytes).map(Some)
+    }
+}
+
+// Yields the blocks in the order they were written. Reading stops at the
+// first error, e.g. a record cut short at the end of the file.
+impl<R: Read> Iterator for BlockFileReader<R> {
+    type Item = IroncResult<HashedBlock>;
+
+    fn next(&mut self) -> Option<IroncResult<HashedBlock>> {
+        if self.done {
+            return None;
+        }
+        match self.read_block() {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+// Writes blocks in the format BlockFileReader reads.
+pub struct BlockFileWriter<W: Write> {
+    writer: W
+}
+
+impl<W: Write> BlockFileWriter<W> {
+    pub fn new(writer: W) -> BlockFileWriter<W> {
+        BlockFileWriter { writer: writer }
+    }
+
+    pub fn write_block(&mut self, block: &HashedBlock) -> IroncResult<()> {
+        let record = try!(block.to_bytes());
+        let mut prefix = vec![];
+        encode_varint(record.len() as u64, &mut prefix);
+        try!(self.writer.write_all(&prefix));
+        try!(self.writer.write_all(&record));
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W { self.writer }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> IroncResult<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(FromError::from_error(err))
+        }
+    }
+}
+
+fn truncated(what: &str) -> IroncError {
+    IroncError::with_kind(ErrorKind::Decode, &format!(
+        "Block file is truncated in the middle of a {}.", what))
+}
+
+/*****  Tests  *****/
+
+use block::GenesisBuilder;
+use crypto::{HashDigest, gen_keypair};
+
+// Also returns where each record starts.
+fn make_block_file() -> (Vec<HashedBlock>, Vec<u8>, Vec<usize>) {
+    let (pk, _) = gen_keypair();
+    let mut blocks = vec![];
+    for tokens in range(1, 4) {
+        let mut builder = GenesisBuilder::new();
+        builder.add_transfer(pk.clone(), tokens);
+        blocks.push(builder.build().unwrap());
+    }
+    let mut bytes = vec![];
+    let mut record_starts = vec![];
+    for block in blocks.iter() {
+        record_starts.push(bytes.len());
+        let record = block.to_bytes().unwrap();
+        encode_varint(record.len() as u64, &mut bytes);
+        bytes.push_all(&record);
+    }
+    (blocks, bytes, record_starts)
+}
+
+fn block_hashes(blocks: &[HashedBlock]) -> Vec<HashDigest> {
+    blocks.iter().map(|block| block.decode_hash().unwrap()).collect()
+}
+
+#[test]
+fn test_block_file_reader() {
+    let (blocks, bytes, _) = make_block_file();
+    let read: Vec<HashedBlock> = BlockFileReader::new(&bytes[..])
+        .map(|block| block.unwrap()).collect();
+    assert_eq!(block_hashes(&blocks), block_hashes(&read));
+    assert_eq!(0, BlockFileReader::new(&b""[..]).count());
+}
+
+#[test]
+fn test_block_file_reader_truncated() {
+    let (blocks, bytes, record_starts) = make_block_file();
+    let second = record_starts[1];
+    let first_hash = blocks[0].decode_hash().unwrap();
+    // A block takes more than 127 bytes, so its length takes two.
+    let cuts = [(second + 1, "record length"), (second + 10, "block"),
+                (record_starts[2] - 1, "block")];
+    for &(cut, what) in cuts.iter() {
+        let mut reader = BlockFileReader::new(&bytes[..cut]);
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first_hash, first.decode_hash().unwrap());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(ErrorKind::Decode, err.kind());
+        assert!(format!("{}", err).ends_with(&format!("a {}.", what)));
+        assert!(reader.next().is_none());
+    }
+
+    // Not truncated, but the second block's magic is corrupt.
+    let mut corrupt = bytes.clone();
+    corrupt[second + 2] ^= 0xff;
+    let results: Vec<IroncResult<HashedBlock>> =
+        BlockFileReader::new(&corrupt[..]).collect();
+    assert_eq!(2, results.len());
+    assert!(results[0].is_ok());
+    assert_eq!(ErrorKind::Decode, results[1].as_ref().unwrap_err().kind());
+}
+
+#[test]
+fn test_block_file_round_trip() {
+    let (blocks, bytes, _) = make_block_file();
+    let mut writer = BlockFileWriter::new(vec![]);
+    for block in blocks.iter() {
+        writer.write_block(block).unwrap();
+    }
+    let written = writer.into_inner();
+    assert_eq!(bytes, written);
+
+    let read: Vec<HashedBlock> = BlockFileReader::new(&written[..])
+        .map(|block| block.unwrap()).collect();
+    assert_eq!(block_hashes(&blocks), block_hashes(&read));
+    assert!(read.iter().all(|block| block.verify().is_ok()));
+}
@@ -13,8 +13,8 @@ use store::{MessageStore, KeyValueStore, ProtobufStore};
 const GENESIS_FIELD: &'static str = "meta:genesis";
 const HEAD_FIELD: &'static str = "meta:head";
 
-fn make_genesis_block_diff(genesis: HashedBlock)
-                           -> IroncResult<BlockWithDiff>
+pub fn make_genesis_block_diff(genesis: HashedBlock)
+                               -> IroncResult<BlockWithDiff>
 {
     try!(genesis.verify());
     let num_tx = genesis.get_block().get_transactions().len();
@@ -66,7 +66,7 @@ fn format_balance_key(address: &PublicKey) -> String {
 }
 
 fn format_block_key(block_hash: &HashDigest) -> String {
-    format!("b:{}", block_hash)
+    format!("b:{}", block_hash.to_base64())
 }
 
 pub struct BlockTreeStore<Store: KeyValueStore> {
@@ -295,7 +295,7 @@ fn test_make_genesis_block_diff_empty() {
     use block::GenesisBuilder;
 
     let builder = GenesisBuilder::new();
-    let genesis = builder.build();
+    let genesis = builder.build().unwrap();
     let maybe_patch = make_genesis_block_diff(genesis);
     assert!(maybe_patch.is_ok());
     assert!(0 == maybe_patch.unwrap().get_diff().len());
@@ -313,7 +313,7 @@ fn test_make_genesis_patch_non_unqiue_dest() {
     builder.add_transfer(pk1.clone(), 101);
     builder.add_transfer(pk2.clone(), 271);
     builder.add_transfer(pk1.clone(), 5000);
-    let genesis = builder.build();
+    let genesis = builder.build().unwrap();
     let mut block_diff = make_genesis_block_diff(genesis).unwrap();
     assert_eq!(2, block_diff.get_diff().len());
     let patch_map: HashMap<PublicKey, BalancePatch> =
@@ -349,7 +349,7 @@ fn test_genesis_builder() {
     builder.add_transfer(pk1, 10);
     builder.add_transfer(pk2, 22);
     builder.add_transfer(pk3, 107);
-    let genesis = builder.build();
+    let genesis = builder.build().unwrap();
 
     assert!(genesis.verify().is_ok());
     let txs = genesis.get_block().get_transactions();
@@ -371,7 +371,7 @@ fn test_blocktree_sets_head_and_genesis() {
     let mut builder = GenesisBuilder::new();
     builder.add_transfer(pk1, 100);
     builder.add_transfer(pk2, 100);
-    let genesis = builder.build();
+    let genesis = builder.build().unwrap();
     assert!(genesis.verify().is_ok());
 
     let kv_store = HashMap::<Vec<u8>, Vec<u8>>::new();
@@ -417,7 +417,7 @@ fn test_blocktree_insert_and_snapshot_at() {
     builder.add_transfer(pk1.clone(), 980);
     builder.add_transfer(pk2.clone(), 73);
     builder.add_transfer(pk3.clone(), 10);
-    let genesis = builder.build();
+    let genesis = builder.build().unwrap();
     let genesis_hash = genesis.decode_hash().unwrap();
     genesis.verify().unwrap();
 
@@ -0,0 +1,81 @@
+use std::cmp::max;
+use std::iter::repeat;
+
+use crypto::HashDigest;
+
+// With 10 bits per item and 7 hashes, about 1% of the ids which were not
+// inserted are reported as present.
+const BITS_PER_ITEM: usize = 10;
+const NUM_HASHES: usize = 7;
+const MIN_BITS: usize = 64;
+
+// A bloom filter over hash digests, e.g. the ids of a block's transactions.
+// The digests are already uniformly distributed, so the bit indices are
+// read straight out of the digest rather than rehashing it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize) -> BloomFilter {
+        let num_bits = max(MIN_BITS, expected_items * BITS_PER_ITEM);
+        BloomFilter { bits: repeat(0u8).take((num_bits + 7) / 8).collect() }
+    }
+
+    pub fn insert(&mut self, digest: &HashDigest) {
+        for hash_index in range(0, NUM_HASHES) {
+            let bit = self.bit_index(digest, hash_index);
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    // Never false for a digest which was inserted.
+    pub fn might_contain(&self, digest: &HashDigest) -> bool {
+        range(0, NUM_HASHES).all(|hash_index| {
+            let bit = self.bit_index(digest, hash_index);
+            self.bits[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    pub fn num_bits(&self) -> usize { self.bits.len() * 8 }
+
+    fn bit_index(&self, digest: &HashDigest, hash_index: usize) -> usize {
+        let bytes = &digest.0[4 * hash_index..4 * hash_index + 4];
+        let mut value = 0u32;
+        for &byte in bytes.iter().rev() {
+            value = (value << 8) | byte as u32;
+        }
+        value as usize % self.num_bits()
+    }
+}
+
+/*****  Tests  *****/
+
+use crypto::hash;
+use sodiumoxide::randombytes::randombytes;
+
+#[test]
+fn test_bloom_filter_no_false_negatives() {
+    let digests: Vec<HashDigest> = range(0, 200)
+        .map(|_| hash(&randombytes(16))).collect();
+    let mut filter = BloomFilter::new(digests.len());
+    assert_eq!(2000, filter.num_bits());
+    for digest in digests.iter() {
+        filter.insert(digest);
+    }
+    assert!(digests.iter().all(|digest| filter.might_contain(digest)));
+
+    let false_positives = range(0, 1000)
+        .filter(|_| filter.might_contain(&hash(&randombytes(16)))).count();
+    assert!(false_positives < 50);
+}
+
+#[test]
+fn test_bloom_filter_empty() {
+    let filter = BloomFilter::new(0);
+    assert_eq!(MIN_BITS, filter.num_bits());
+    for _ in range(0, 100) {
+        assert!(!filter.might_contain(&hash(&randombytes(16))));
+    }
+}
@@ -0,0 +1,1923 @@
+use std::ascii::AsciiExt;
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::mem;
+use std::slice::Iter;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::usize;
+
+use rustc_serialize::hex::ToHex;
+
+use balance::{LedgerReader, LedgerSnapshot, Patchable};
+use block::{BLOCK_REWARD, Clock, HashedBlockExt, SystemClock, apply_patch,
+            diff_blocks};
+use blockfile::{BlockFileReader, BlockFileWriter};
+use blocktree::make_genesis_block_diff;
+use crypto::{HashDigest, PublicKey, SecretKey};
+use error::{ErrorKind, IroncError, IroncResult};
+use ironcoin_pb::{Balance, BlockPatch, HashedBlock, Transaction};
+use tx::TransactionExt;
+
+pub const MAX_TIMESTAMP_DRIFT: i64 = 10 * 60;
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 1 << 20;
+pub const DEFAULT_MAX_TX_SIZE: usize = 1 << 16;
+
+type Ledger = HashMap<PublicKey, Balance>;
+
+pub struct Blockchain {
+    blocks: Vec<HashedBlock>,
+    tip: HashDigest,
+    block_index: HashMap<HashDigest, usize>,
+    tx_index: HashMap<HashDigest, (usize, usize)>,
+    ledger: Ledger,
+    stale_blocks: Vec<HashedBlock>,
+    max_block_size: usize,
+    max_tx_size: usize,
+    checkpoints: BTreeMap<u32, HashDigest>,
+    pruned_height: u32,
+    require_coinbase: bool,
+    counters: VerificationCounters
+}
+
+// How many times append() rejected blocks, by the kind of error.
+pub struct VerificationCounters {
+    other: AtomicUsize,
+    conservation: AtomicUsize,
+    decode: AtomicUsize,
+    double_spend: AtomicUsize,
+    hash_mismatch: AtomicUsize,
+    signature_invalid: AtomicUsize
+}
+
+impl VerificationCounters {
+    pub fn new() -> VerificationCounters {
+        VerificationCounters {
+            other: AtomicUsize::new(0),
+            conservation: AtomicUsize::new(0),
+            decode: AtomicUsize::new(0),
+            double_spend: AtomicUsize::new(0),
+            hash_mismatch: AtomicUsize::new(0),
+            signature_invalid: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn record(&self, kind: ErrorKind) {
+        self.counter(kind).fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, kind: ErrorKind) -> usize {
+        self.counter(kind).load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        [ErrorKind::Other, ErrorKind::Conservation, ErrorKind::Decode,
+         ErrorKind::DoubleSpend, ErrorKind::HashMismatch,
+         ErrorKind::SignatureInvalid].iter().map(|&kind| self.get(kind))
+            .fold(0, |total, count| total + count)
+    }
+
+    fn counter(&self, kind: ErrorKind) -> &AtomicUsize {
+        match kind {
+            ErrorKind::Other => &self.other,
+            ErrorKind::Conservation => &self.conservation,
+            ErrorKind::Decode => &self.decode,
+            ErrorKind::DoubleSpend => &self.double_spend,
+            ErrorKind::HashMismatch => &self.hash_mismatch,
+            ErrorKind::SignatureInvalid => &self.signature_invalid
+        }
+    }
+}
+
+impl Blockchain {
+    pub fn new(genesis: HashedBlock) -> IroncResult<Blockchain> {
+        try!(genesis.verify_is_genesis());
+        let genesis_diff = try!(make_genesis_block_diff(genesis.clone()));
+        let genesis_hash = try!(genesis.decode_hash());
+        let mut chain = Blockchain {
+            blocks: vec![],
+            tip: genesis_hash.clone(),
+            block_index: HashMap::new(),
+            tx_index: HashMap::new(),
+            ledger: HashMap::new(),
+            stale_blocks: vec![],
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            max_tx_size: DEFAULT_MAX_TX_SIZE,
+            checkpoints: BTreeMap::new(),
+            pruned_height: 0,
+            require_coinbase: false,
+            counters: VerificationCounters::new()
+        };
+        for patch in genesis_diff.get_diff().iter() {
+            try!(chain.ledger.apply_patch(patch.clone()));
+        }
+        chain.push_block(genesis_hash, genesis);
+        Ok(chain)
+    }
+
+    // As new, but only accepts the genesis a network is known to start from.
+    pub fn with_genesis_hash(genesis: HashedBlock, expected_hash: &HashDigest)
+                             -> IroncResult<Blockchain> {
+        let genesis_hash = try!(genesis.decode_hash());
+        if genesis_hash != *expected_hash {
+            return Err(IroncError::with_kind(ErrorKind::HashMismatch, &format!(
+                "Genesis block has hash {} != {} (expected)",
+                genesis_hash, expected_hash)));
+        }
+        Blockchain::new(genesis)
+    }
+
+    // Loads a chain written by export_to(), verifying every block as it is
+    // appended.
+    pub fn import_from<R: Read>(reader: R) -> IroncResult<Blockchain> {
+        let mut blocks = BlockFileReader::new(reader);
+        let genesis = match blocks.next() {
+            Some(genesis) => try!(genesis),
+            None => return Err(IroncError::with_kind(
+                ErrorKind::Decode, "Block file contains no blocks."))
+        };
+        let mut chain = try!(Blockchain::new(genesis));
+        for block in blocks {
+            try!(chain.append(try!(block)));
+        }
+        Ok(chain)
+    }
+
+    // Writes the main chain, genesis first, in the block file format. A
+    // pruned chain can't be read back, so it can't be exported either.
+    pub fn export_to<W: Write>(&self, writer: W) -> IroncResult<()> {
+        if self.pruned_height > 0 {
+            return Err(IroncError::new(&format!(
+                "Cannot export a chain pruned below height {}.",
+                self.pruned_height)));
+        }
+        let mut writer = BlockFileWriter::new(writer);
+        for block in self.blocks.iter() {
+            try!(writer.write_block(block));
+        }
+        Ok(())
+    }
+
+    pub fn append(&mut self, block: HashedBlock) -> IroncResult<()> {
+        self.append_all(vec![block])
+    }
+
+    // For block producers: makes `block` the successor of the tip, at the
+    // current time (or the tip's, if later), then reseals and appends it.
+    // It is verified like any other block. Any proof of work is cleared, as
+    // resealing invalidates it. `block` is only updated if it is appended.
+    pub fn seal_and_append(&mut self, block: &mut HashedBlock,
+                           secret_key: &SecretKey) -> IroncResult<()> {
+        self.seal_and_append_with_clock(block, secret_key, &SystemClock)
+    }
+
+    pub fn seal_and_append_with_clock<C: Clock>(
+        &mut self, block: &mut HashedBlock, secret_key: &SecretKey, clock: &C)
+        -> IroncResult<()>
+    {
+        let (height, tip_timestamp) = {
+            let tip = &self.blocks[self.blocks.len() - 1];
+            (tip.get_height() + 1, tip.get_block().get_timestamp())
+        };
+        let mut sealed = block.clone();
+        sealed.mut_signed_block().clear_difficulty();
+        sealed.mut_signed_block().clear_nonce();
+        sealed.mut_signed_block().mut_block().set_height(height);
+        sealed.mut_signed_block().mut_block().set_timestamp(
+            max(clock.now().sec, tip_timestamp));
+        let tip_hash = self.tip.clone();
+        sealed.rechain(&tip_hash, secret_key);
+        try!(self.append(sealed.clone()));
+        *block = sealed;
+        Ok(())
+    }
+
+    // Either all the blocks are appended or, on error, the chain is left
+    // unchanged. The ledger changes are accumulated in a single snapshot which
+    // is only applied once every block has been checked.
+    pub fn append_all(&mut self, blocks: Vec<HashedBlock>) -> IroncResult<()> {
+        let appended = self.append_all_unrecorded(blocks);
+        if let Err(ref err) = appended {
+            self.counters.record(err.kind());
+        }
+        appended
+    }
+
+    // Rebuilds the blocks from patches made by compute_sync_patches() for
+    // this chain's tip, then appends them as append_all() would.
+    pub fn apply_sync_patches(&mut self, patches: Vec<BlockPatch>)
+                              -> IroncResult<()> {
+        let mut blocks: Vec<HashedBlock> = vec![];
+        for patch in patches.iter() {
+            let block = {
+                let base = blocks.last()
+                    .unwrap_or(&self.blocks[self.blocks.len() - 1]);
+                try!(apply_patch(base, patch))
+            };
+            blocks.push(block);
+        }
+        self.append_all(blocks)
+    }
+
+    fn append_all_unrecorded(&mut self, blocks: Vec<HashedBlock>)
+                             -> IroncResult<()> {
+        let mut block_hashes = vec![];
+        let patches = {
+            let mut snapshot = LedgerSnapshot::new(&self.ledger);
+            let mut parent = &self.blocks[self.blocks.len() - 1];
+            for block in blocks.iter() {
+                try!(block.verify_size(self.max_block_size));
+                try!(block.verify_transaction_sizes(self.max_tx_size));
+                try!(verify_checkpoint(&self.checkpoints, block));
+                if self.require_coinbase {
+                    try!(block.verify_coinbase(BLOCK_REWARD));
+                }
+                try!(verify_successor(parent, block));
+                try!(apply_to_ledger(&mut snapshot, block));
+                block_hashes.push(try!(block.decode_hash()));
+                parent = block;
+            }
+            snapshot.make_patches()
+        };
+        for patch in patches.into_iter() {
+            try!(self.ledger.apply_patch(patch));
+        }
+        for (block_hash, block) in
+            block_hashes.into_iter().zip(blocks.into_iter())
+        {
+            self.push_block(block_hash, block);
+        }
+        Ok(())
+    }
+
+    // Switches to `branch` if it forks off this chain and has more work than
+    // the blocks it would replace, so a shorter branch can win if it is
+    // heavier. The ledger has no undo log, so the new chain is replayed from
+    // genesis and only swapped in once the whole branch has been verified.
+    // The blocks which were switched away from are kept in stale_blocks() so
+    // they can be reorganised back to later.
+    pub fn try_reorg(&mut self, branch: Vec<HashedBlock>) -> IroncResult<bool> {
+        if self.pruned_height > 0 {
+            return Err(IroncError::new(
+                "Cannot reorganise a pruned chain, it cannot be replayed."));
+        }
+        let fork_position = {
+            let first = match branch.first() {
+                Some(first) => first,
+                None => return Err(IroncError::new("Empty branch."))
+            };
+            let previous_hash = try!(first.decode_previous());
+            match self.block_index.get(&previous_hash) {
+                Some(&position) => position,
+                None => return Err(IroncError::new(&format!(
+                    "Branch forks from {} which is not in the chain.",
+                    previous_hash)))
+            }
+        };
+        if chain_work(&branch) <=
+            chain_work(&self.blocks[fork_position + 1..])
+        {
+            return Ok(false);
+        }
+        // The blocks up to the fork were accepted under the settings at the
+        // time, only the branch is held to the current ones.
+        let mut reorged = try!(Blockchain::new(self.blocks[0].clone()));
+        reorged.set_max_block_size(usize::MAX);
+        reorged.set_max_tx_size(usize::MAX);
+        reorged.checkpoints = self.checkpoints.clone();
+        let replayed = reorged.append_all_unrecorded(
+            self.blocks[1..fork_position + 1].to_vec());
+        let (max_block_size, max_tx_size, require_coinbase) =
+            (self.max_block_size, self.max_tx_size, self.require_coinbase);
+        let replayed = replayed.and_then(|()| {
+            reorged.set_max_block_size(max_block_size);
+            reorged.set_max_tx_size(max_tx_size);
+            reorged.set_require_coinbase(require_coinbase);
+            reorged.append_all_unrecorded(branch)
+        });
+        // Count a rejection here, as reorged and its counters are dropped.
+        if let Err(ref err) = replayed {
+            self.counters.record(err.kind());
+        }
+        try!(replayed);
+
+        let mut stale_blocks = mem::replace(&mut self.stale_blocks, vec![]);
+        stale_blocks.extend(self.blocks.split_off(fork_position + 1).into_iter());
+        reorged.stale_blocks = stale_blocks;
+        reorged.counters =
+            mem::replace(&mut self.counters, VerificationCounters::new());
+        *self = reorged;
+        Ok(true)
+    }
+
+    pub fn stale_blocks(&self) -> &[HashedBlock] { &self.stale_blocks }
+
+    pub fn stats(&self) -> &VerificationCounters { &self.counters }
+
+    // Only applies to blocks appended from now on, including those of a
+    // branch passed to try_reorg, but not to the blocks it replays. The
+    // genesis is exempt.
+    pub fn set_max_block_size(&mut self, max_block_size: usize) {
+        self.max_block_size = max_block_size;
+    }
+
+    pub fn max_block_size(&self) -> usize { self.max_block_size }
+
+    // As with the block size, the genesis is exempt.
+    pub fn set_max_tx_size(&mut self, max_tx_size: usize) {
+        self.max_tx_size = max_tx_size;
+    }
+
+    pub fn max_tx_size(&self) -> usize { self.max_tx_size }
+
+    // Whether blocks appended from now on must pay BLOCK_REWARD through a
+    // coinbase, rather than the fees going to the staker.
+    pub fn set_require_coinbase(&mut self, require_coinbase: bool) {
+        self.require_coinbase = require_coinbase;
+    }
+
+    // Blocks appended later must match the checkpoint at their height, and
+    // verify_blocks() trusts the blocks up to a checkpoint.
+    pub fn set_checkpoint(&mut self, height: u32, block_hash: HashDigest) {
+        self.checkpoints.insert(height, block_hash);
+    }
+
+    pub fn verify_blocks(&self, blocks: &[HashedBlock]) -> IroncResult<()> {
+        verify_chain_with_checkpoints(blocks, &self.checkpoints)
+    }
+
+    // Drops the transactions of the blocks below `before_height`, keeping
+    // their headers. Their transfers are already in the ledger, which is
+    // updated as blocks are appended, so balances are unaffected, but the
+    // transactions can no longer be looked up and the chain can no longer be
+    // reorganised.
+    pub fn prune(&mut self, before_height: u32) {
+        let end = min(before_height as usize, self.blocks.len());
+        for position in range(self.pruned_height as usize, end) {
+            for tx in
+                self.blocks[position].get_block().get_transactions().iter()
+            {
+                self.tx_index.remove(&tx.compute_id());
+            }
+            self.blocks[position].mut_signed_block().mut_block()
+                .clear_transactions();
+        }
+        self.pruned_height = max(self.pruned_height, end as u32);
+    }
+
+    // The blocks below this height have had their transactions pruned.
+    pub fn pruned_height(&self) -> u32 { self.pruned_height }
+
+    // Re-verifies the blocks held, pruned blocks are only verified by header.
+    pub fn verify(&self) -> IroncResult<()> {
+        verify_chain_from(&self.blocks, &self.checkpoints, self.pruned_height)
+    }
+
+    // Whether the transaction could go in the next block, i.e. it verifies
+    // and its transfers can be applied to the current ledger. Nothing is
+    // changed either way.
+    pub fn would_accept(&self, transaction: &Transaction) -> IroncResult<()> {
+        try!(transaction.verify_op_sequence());
+        try!(transaction.verify_signatures());
+        try!(transaction.verify_bounty());
+        let mut snapshot = LedgerSnapshot::new(&self.ledger);
+        snapshot.apply_transaction(transaction)
+    }
+
+    pub fn get_balance(&self, public_key: &PublicKey) -> Balance {
+        self.ledger.get_balance(public_key).unwrap()
+    }
+
+    // The op_index the next transfer from `public_key` must have. The ledger
+    // requires each account's transfers to count up from 0 across blocks, so
+    // a signed transfer can't be replayed once it is in the chain.
+    pub fn next_op_index(&self, public_key: &PublicKey) -> u32 {
+        self.get_balance(public_key).get_op_index()
+    }
+
+    // The ledger is kept up to date as blocks are appended (and rebuilt by
+    // try_reorg), so this doesn't scan the chain.
+    pub fn balance_of(&self, public_key: &PublicKey) -> u64 {
+        self.get_balance(public_key).get_tokens()
+    }
+
+    // Only called once the block is known to be valid, so the indexes never
+    // refer to blocks which were rejected.
+    fn push_block(&mut self, block_hash: HashDigest, block: HashedBlock) {
+        let position = self.blocks.len();
+        for (tx_position, tx) in
+            block.get_block().get_transactions().iter().enumerate()
+        {
+            self.tx_index.insert(tx.compute_id(), (position, tx_position));
+        }
+        self.block_index.insert(block_hash.clone(), position);
+        self.tip = block_hash;
+        self.blocks.push(block);
+    }
+
+    pub fn block_by_hash(&self, block_hash: &HashDigest)
+                         -> Option<&HashedBlock> {
+        self.block_index.get(block_hash).map(|&position| &self.blocks[position])
+    }
+
+    // Blocks are returned from the genesis up. The prefix may have an odd
+    // number of digits and is case insensitive.
+    pub fn find_blocks_by_hash_prefix(&self, prefix_hex: &str)
+                                      -> IroncResult<Vec<&HashedBlock>> {
+        if !prefix_hex.chars().all(|digit| digit.is_digit(16)) {
+            return Err(IroncError::with_kind(ErrorKind::Decode, &format!(
+                "Invalid hash prefix \"{}\": not hex.", prefix_hex)));
+        }
+        let prefix = prefix_hex.to_ascii_lowercase();
+        Ok(self.blocks.iter().filter(
+            |block| block.get_hash().to_hex().starts_with(&prefix)).collect())
+    }
+
+    pub fn transaction_by_id(&self, tx_id: &HashDigest)
+                             -> Option<&Transaction> {
+        self.tx_index.get(tx_id).map(|&(position, tx_position)| {
+            &self.blocks[position].get_block().get_transactions()[tx_position]
+        })
+    }
+
+    // Both iterate from the genesis block to the tip.
+    pub fn blocks<'a>(&'a self) -> Iter<'a, HashedBlock> { self.blocks.iter() }
+
+    pub fn transactions<'a>(&'a self)
+                            -> Box<Iterator<Item=&'a Transaction> + 'a> {
+        Box::new(self.blocks.iter().flat_map(
+            |block| block.get_block().get_transactions().iter()))
+    }
+
+    pub fn tip(&self) -> &HashDigest { &self.tip }
+
+    pub fn total_work(&self) -> HashDigest { chain_work(&self.blocks) }
+
+    pub fn tip_height(&self) -> u32 {
+        self.blocks[self.blocks.len() - 1].get_height()
+    }
+}
+
+// A handle on a chain for several threads, e.g. serving queries while blocks
+// are appended. Protobuf messages aren't Sync, so the chain can't be behind
+// an RwLock: reads take turns through a Mutex, each one short. Cloning the
+// handle shares the same chain.
+#[derive(Clone)]
+pub struct SharedChain {
+    chain: Arc<Mutex<Blockchain>>
+}
+
+impl SharedChain {
+    pub fn new(chain: Blockchain) -> SharedChain {
+        SharedChain { chain: Arc::new(Mutex::new(chain)) }
+    }
+
+    pub fn append(&self, block: HashedBlock) -> IroncResult<()> {
+        self.lock().append(block)
+    }
+
+    pub fn append_all(&self, blocks: Vec<HashedBlock>) -> IroncResult<()> {
+        self.lock().append_all(blocks)
+    }
+
+    // For queries which need a consistent view of several things at once.
+    pub fn with_chain<T, F: FnOnce(&Blockchain) -> T>(&self, query: F) -> T {
+        query(&*self.lock())
+    }
+
+    pub fn tip(&self) -> HashDigest { self.lock().tip().clone() }
+
+    pub fn tip_height(&self) -> u32 { self.lock().tip_height() }
+
+    pub fn block_by_hash(&self, block_hash: &HashDigest)
+                         -> Option<HashedBlock> {
+        self.lock().block_by_hash(block_hash).map(|block| block.clone())
+    }
+
+    pub fn get_balance(&self, public_key: &PublicKey) -> Balance {
+        self.lock().get_balance(public_key)
+    }
+
+    fn lock(&self) -> MutexGuard<Blockchain> { self.chain.lock().unwrap() }
+}
+
+// The patches taking a peer whose tip is `local_tip` to the tip of `remote`,
+// each against the block before it, so only the transactions new to each
+// block are sent. `local_tip` must be on remote's main chain.
+pub fn compute_sync_patches(local_tip: &HashDigest, remote: &Blockchain)
+                            -> IroncResult<Vec<BlockPatch>> {
+    let position = match remote.block_index.get(local_tip) {
+        Some(&position) => position,
+        None => return Err(IroncError::new(&format!(
+            "Cannot sync from {} which is not in the chain.", local_tip)))
+    };
+    if (remote.pruned_height as usize) > position {
+        return Err(IroncError::new(&format!(
+            "Cannot sync from {}, the chain is pruned below height {}.",
+            local_tip, remote.pruned_height)));
+    }
+    Ok(remote.blocks[position..].windows(2)
+       .map(|pair| diff_blocks(&pair[0], &pair[1])).collect())
+}
+
+// The expected number of hashes needed to mine the block, 2^difficulty, as a
+// little endian 512 bit integer. A block that is not mined counts as 1.
+pub fn block_work(block: &HashedBlock) -> HashDigest {
+    let mut work = HashDigest::zero();
+    let difficulty = min(block.get_signed_block().get_difficulty() as usize,
+                         work.0.len() * 8 - 1);
+    work.0[difficulty / 8] = 1 << (difficulty % 8);
+    work
+}
+
+pub fn chain_work(blocks: &[HashedBlock]) -> HashDigest {
+    let mut total_work = HashDigest::zero();
+    for block in blocks.iter() {
+        total_work.add_in_place(&block_work(block));
+    }
+    total_work
+}
+
+fn apply_to_ledger(snapshot: &mut LedgerSnapshot<Ledger>, block: &HashedBlock)
+                   -> IroncResult<()> {
+    match snapshot.apply_block(block) {
+        Ok(_) => Ok(()),
+        Err(ref err) if err.kind() == ErrorKind::DoubleSpend => {
+            Err(IroncError::with_kind(ErrorKind::DoubleSpend, &format!(
+                "Block {} spends funds which are not available: {}",
+                try!(block.decode_hash()), err)))
+        },
+        Err(err) => Err(err)
+    }
+}
+
+fn verify_successor(parent: &HashedBlock, block: &HashedBlock)
+                    -> IroncResult<()> {
+    try!(block.verify());
+    verify_link(parent, block)
+}
+
+fn verify_link(parent: &HashedBlock, block: &HashedBlock) -> IroncResult<()> {
+    let parent_hash = try!(parent.decode_hash());
+    let previous_hash = try!(block.decode_previous());
+    if previous_hash != parent_hash {
+        return Err(IroncError::new(&format!(
+            "Block has previous {} != {} (parent)",
+            previous_hash, parent_hash)));
+    }
+    let parent_height = parent.get_height();
+    if block.get_height() != parent_height + 1 {
+        return Err(IroncError::new(&format!(
+            "Invalid block height {} (expected {})",
+            block.get_height(), parent_height + 1)));
+    }
+    block.verify_timestamp(parent.get_block().get_timestamp(),
+                           MAX_TIMESTAMP_DRIFT)
+}
+
+fn verify_checkpoint(checkpoints: &BTreeMap<u32, HashDigest>,
+                     block: &HashedBlock) -> IroncResult<()> {
+    match checkpoints.get(&block.get_height()) {
+        Some(checkpoint_hash) => {
+            let block_hash = try!(block.decode_hash());
+            if block_hash != *checkpoint_hash {
+                return Err(IroncError::with_kind(
+                    ErrorKind::HashMismatch, &format!(
+                        "Block at height {} has hash {} != {} (checkpoint)",
+                        block.get_height(), block_hash, checkpoint_hash)));
+            }
+            Ok(())
+        },
+        None => Ok(())
+    }
+}
+
+pub fn verify_chain(blocks: &[HashedBlock]) -> IroncResult<()> {
+    verify_chain_with_checkpoints(blocks, &BTreeMap::new())
+}
+
+// The blocks up to the highest checkpoint among `blocks` are committed to by
+// the checkpoint's hash through their links, so only their hashes, merkle
+// roots and links are checked and the signatures are skipped.
+pub fn verify_chain_with_checkpoints(blocks: &[HashedBlock],
+                                     checkpoints: &BTreeMap<u32, HashDigest>)
+                                     -> IroncResult<()> {
+    verify_chain_from(blocks, checkpoints, 0)
+}
+
+// As verify_chain_with_checkpoints, but the blocks below `pruned_height` have
+// no transactions and are verified by header.
+fn verify_chain_from(blocks: &[HashedBlock],
+                     checkpoints: &BTreeMap<u32, HashDigest>,
+                     pruned_height: u32) -> IroncResult<()> {
+    if blocks.len() == 0 {
+        return Err(IroncError::new("Cannot verify an empty chain."));
+    }
+    let describe_failure = |index: usize, err: IroncError| {
+        let block_hash = match blocks[index].decode_hash() {
+            Ok(block_hash) => format!("{}", block_hash),
+            Err(_) => String::from_str("<invalid hash>")
+        };
+        IroncError::with_kind(err.kind(), &format!(
+            "Block {} ({}) failed verification: {}", index, block_hash, err))
+    };
+    let trusted_height = blocks.iter().rev()
+        .map(|block| block.get_height())
+        .find(|height| checkpoints.contains_key(height));
+    for index in range(0, blocks.len()) {
+        let block = &blocks[index];
+        let trusted = match trusted_height {
+            Some(trusted_height) => block.get_height() <= trusted_height,
+            None => false
+        };
+        let verified = verify_checkpoint(checkpoints, block).and_then(|_| {
+            // A pruned block has no transactions left to match its merkle
+            // root, even when a checkpoint vouches for it.
+            if block.get_height() < pruned_height {
+                block.verify_header()
+            } else if trusted {
+                block.verify_hash().and_then(|_| block.verify_merkle_root())
+            } else {
+                block.verify()
+            }
+        }).and_then(|_| {
+            if index > 0 { verify_link(&blocks[index - 1], block) }
+            else { Ok(()) }
+        });
+        match verified {
+            Ok(_) => {},
+            Err(err) => return Err(describe_failure(index, err))
+        }
+    }
+    Ok(())
+}
+
+/*****  Tests  *****/
+
+use std::error::Error;
+use std::thread;
+
+use block::{GenesisBuilder, SignedBlockExt};
+use crypto::gen_keypair;
+use time::now_utc;
+use tx::{TransactionBuilder, make_coinbase};
+
+fn make_block(previous: &HashedBlock, transactions: Vec<Transaction>,
+              staker_sk: &SecretKey) -> HashedBlock {
+    let mut block = HashedBlock::new();
+    block.set_previous_block(&previous.decode_hash().unwrap());
+    block.mut_signed_block().mut_block().set_height(previous.get_height() + 1);
+    block.mut_signed_block().mut_block().set_timestamp(
+        previous.get_block().get_timestamp());
+    for transaction in transactions.into_iter() {
+        block.mut_signed_block().mut_block().mut_transactions().push(transaction);
+    }
+    block.mut_signed_block().sign(staker_sk);
+    block.compute_hash();
+    block
+}
+
+#[test]
+fn test_blockchain_append() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let genesis_hash = genesis.decode_hash().unwrap();
+
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(genesis_hash, *chain.tip());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let block1 = make_block(&genesis, vec![tx_builder.build().unwrap()], &sk1);
+    let block1_hash = block1.decode_hash().unwrap();
+    chain.append(block1).unwrap();
+    assert_eq!(block1_hash, *chain.tip());
+}
+
+#[test]
+fn test_blockchain_append_out_of_order() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let block1 = make_block(&genesis, vec![], &sk);
+    let block2 = make_block(&block1, vec![], &sk);
+
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert!(chain.append(block2.clone()).is_err());
+    assert_eq!(genesis.decode_hash().unwrap(), *chain.tip());
+    chain.append(block1).unwrap();
+    chain.append(block2).unwrap();
+}
+
+#[test]
+fn test_blockchain_append_previous_not_tip() {
+    let (_, sk1) = gen_keypair();
+    let (_, sk2) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let block1a = make_block(&genesis, vec![], &sk1);
+    let block1b = make_block(&genesis, vec![], &sk2);
+
+    let mut chain = Blockchain::new(genesis).unwrap();
+    chain.append(block1a.clone()).unwrap();
+    assert!(chain.append(block1b).is_err());
+    assert_eq!(block1a.decode_hash().unwrap(), *chain.tip());
+}
+
+#[test]
+fn test_blockchain_append_invalid_transaction() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let mut transaction = tx_builder.build().unwrap();
+    transaction.clear_signatures();
+    let block1 = make_block(&genesis, vec![transaction], &sk1);
+
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert!(chain.append(block1).is_err());
+    assert_eq!(genesis.decode_hash().unwrap(), *chain.tip());
+}
+
+#[test]
+fn test_blockchain_append_invalid_height() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(0, chain.tip_height());
+
+    let mut skipped = make_block(&genesis, vec![], &sk);
+    skipped.mut_signed_block().mut_block().set_height(2);
+    skipped.mut_signed_block().sign(&sk);
+    skipped.compute_hash();
+    assert!(chain.append(skipped).is_err());
+
+    let mut repeated = make_block(&genesis, vec![], &sk);
+    repeated.mut_signed_block().mut_block().set_height(0);
+    repeated.mut_signed_block().sign(&sk);
+    repeated.compute_hash();
+    assert!(chain.append(repeated).is_err());
+    assert_eq!(0, chain.tip_height());
+
+    chain.append(make_block(&genesis, vec![], &sk)).unwrap();
+    assert_eq!(1, chain.tip_height());
+}
+
+#[test]
+fn test_blockchain_append_invalid_timestamp() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let genesis_timestamp = genesis.get_block().get_timestamp();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+
+    let mut backwards = make_block(&genesis, vec![], &sk);
+    backwards.mut_signed_block().mut_block().set_timestamp(genesis_timestamp - 1);
+    backwards.mut_signed_block().sign(&sk);
+    backwards.compute_hash();
+    assert!(chain.append(backwards).is_err());
+
+    let mut future = make_block(&genesis, vec![], &sk);
+    future.mut_signed_block().mut_block().set_timestamp(
+        now_utc().to_timespec().sec + 60 * 60);
+    future.mut_signed_block().sign(&sk);
+    future.compute_hash();
+    assert!(chain.append(future).is_err());
+    assert_eq!(0, chain.tip_height());
+
+    chain.append(make_block(&genesis, vec![], &sk)).unwrap();
+    assert_eq!(1, chain.tip_height());
+}
+
+#[test]
+fn test_verify_chain() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let mut blocks = vec![builder.build().unwrap()];
+    for op_index in range(0, 4) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        let block = make_block(&blocks[blocks.len() - 1],
+                               vec![tx_builder.build().unwrap()], &sk1);
+        blocks.push(block);
+    }
+    assert!(verify_chain(&blocks).is_ok());
+    assert!(verify_chain(&blocks[..1]).is_ok());
+    assert!(verify_chain(&blocks[2..]).is_ok());
+    assert!(verify_chain(&[]).is_err());
+}
+
+#[test]
+fn test_verify_chain_broken_link() {
+    let (_, sk) = gen_keypair();
+    let mut blocks = vec![GenesisBuilder::new().build().unwrap()];
+    for _ in range(0, 4) {
+        let block = make_block(&blocks[blocks.len() - 1], vec![], &sk);
+        blocks.push(block);
+    }
+    let mut broken = make_block(&blocks[1], vec![], &sk);
+    broken.set_previous_block(&HashDigest::from_u64(1));
+    broken.mut_signed_block().sign(&sk);
+    let broken_hash = broken.compute_hash();
+    blocks[2] = broken;
+
+    let err = verify_chain(&blocks).unwrap_err();
+    assert!(err.description().contains("Block 2"));
+    assert!(err.description().contains(&format!("{}", broken_hash)));
+    assert!(verify_chain(&blocks[..2]).is_ok());
+    assert!(verify_chain(&blocks[3..]).is_ok());
+}
+
+#[test]
+fn test_blockchain_iterators() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let mut chain = Blockchain::new(builder.build().unwrap()).unwrap();
+    let mut op_index = 0;
+    for num_tx in range(0, 4) {
+        let mut transactions = vec![];
+        for _ in range(0, num_tx) {
+            let mut tx_builder = TransactionBuilder::new();
+            tx_builder.add_transfer(&sk1, &pk1, &pk2, 1, op_index);
+            transactions.push(tx_builder.build().unwrap());
+            op_index += 1;
+        }
+        let block = make_block(chain.blocks().last().unwrap(), transactions,
+                               &sk1);
+        chain.append(block).unwrap();
+    }
+
+    let heights: Vec<u32> =
+        chain.blocks().map(|block| block.get_height()).collect();
+    assert_eq!(vec![0, 1, 2, 3, 4], heights);
+
+    let mut manual_count = 0;
+    for block in chain.blocks() {
+        manual_count += block.get_block().get_transactions().len();
+    }
+    assert_eq!(7, manual_count);
+    assert_eq!(manual_count, chain.transactions().count());
+    assert!(chain.transactions().all(|tx| tx.verify_signatures().is_ok()));
+}
+
+#[test]
+fn test_blockchain_lookups() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    let genesis_tx = &genesis.get_block().get_transactions()[0];
+    assert!(chain.block_by_hash(&genesis.decode_hash().unwrap()) ==
+            Some(&genesis));
+    assert!(chain.transaction_by_id(&genesis_tx.compute_id()) ==
+            Some(genesis_tx));
+
+    let mut blocks = vec![];
+    let mut transactions = vec![];
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        let transaction = tx_builder.build().unwrap();
+        let block = make_block(chain.blocks().last().unwrap(),
+                               vec![transaction.clone()], &sk1);
+        chain.append(block.clone()).unwrap();
+        blocks.push(block);
+        transactions.push(transaction);
+    }
+    for (block, transaction) in blocks.iter().zip(transactions.iter()) {
+        let block_hash = block.decode_hash().unwrap();
+        assert!(chain.block_by_hash(&block_hash) == Some(block));
+        assert!(chain.transaction_by_id(&transaction.compute_id()) ==
+                Some(transaction));
+        assert!(chain.transaction_by_id(&block_hash).is_none());
+    }
+
+    // A rejected block must not be indexed.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 3);
+    let rejected_tx = tx_builder.build().unwrap();
+    let rejected = make_block(&genesis, vec![rejected_tx.clone()], &sk1);
+    assert!(chain.append(rejected.clone()).is_err());
+    assert!(chain.block_by_hash(&rejected.decode_hash().unwrap()).is_none());
+    assert!(chain.transaction_by_id(&rejected_tx.compute_id()).is_none());
+    assert!(chain.block_by_hash(&HashDigest::from_u64(1)).is_none());
+}
+
+#[test]
+fn test_blockchain_append_double_spend() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 60, 0);
+    let transfer = tx_builder.build().unwrap();
+    let block1 = make_block(&genesis, vec![transfer.clone()], &sk1);
+    chain.append(block1.clone()).unwrap();
+    assert_eq!(40, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(1, chain.get_balance(&pk1).get_op_index());
+    assert_eq!(60, chain.get_balance(&pk2).get_tokens());
+
+    // Replaying the same transfer in a later block.
+    let replay = make_block(&block1, vec![transfer], &sk1);
+    let err = chain.append(replay).unwrap_err();
+    assert_eq!(ErrorKind::DoubleSpend, err.kind());
+    assert_eq!(block1.decode_hash().unwrap(), *chain.tip());
+    assert_eq!(40, chain.get_balance(&pk1).get_tokens());
+
+    // Spending more than is left.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk3, 41, 1);
+    let overspend =
+        make_block(&block1, vec![tx_builder.build().unwrap()], &sk1);
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.append(overspend).unwrap_err().kind());
+    assert_eq!(0, chain.get_balance(&pk3).get_tokens());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk3, 40, 1)
+        .add_transfer(&sk2, &pk2, &pk3, 60, 0);
+    chain.append(make_block(&block1, vec![tx_builder.build().unwrap()], &sk1))
+        .unwrap();
+    assert_eq!(0, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(0, chain.get_balance(&pk2).get_tokens());
+    assert_eq!(100, chain.get_balance(&pk3).get_tokens());
+}
+
+#[test]
+fn test_blockchain_append_self_transfer_replay() {
+    let (pk1, sk1) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+
+    // Sending tokens to oneself mints nothing but still takes an op_index.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk1, 60, 0);
+    let transfer = tx_builder.build().unwrap();
+    let block1 = make_block(&genesis, vec![transfer.clone()], &sk1);
+    chain.append(block1.clone()).unwrap();
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(1, chain.get_balance(&pk1).get_op_index());
+
+    let replay = make_block(&block1, vec![transfer], &sk1);
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.append(replay).unwrap_err().kind());
+    assert_eq!(block1.decode_hash().unwrap(), *chain.tip());
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+}
+
+#[test]
+fn test_blockchain_append_all() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let genesis_hash = genesis.decode_hash().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+
+    let mut blocks = vec![];
+    for op_index in range(0, 4) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 30, op_index);
+        let parent = blocks.last().unwrap_or(&genesis).clone();
+        blocks.push(
+            make_block(&parent, vec![tx_builder.build().unwrap()], &sk1));
+    }
+
+    // The last block overspends, so none of the batch is appended.
+    assert!(chain.append_all(blocks.clone()).is_err());
+    assert_eq!(genesis_hash, *chain.tip());
+    assert_eq!(0, chain.tip_height());
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(0, chain.get_balance(&pk2).get_tokens());
+    assert!(chain.block_by_hash(&blocks[0].decode_hash().unwrap()).is_none());
+
+    let last_hash = blocks[2].decode_hash().unwrap();
+    blocks.pop();
+    chain.append_all(blocks).unwrap();
+    assert_eq!(last_hash, *chain.tip());
+    assert_eq!(3, chain.tip_height());
+    assert_eq!(10, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(90, chain.get_balance(&pk2).get_tokens());
+    assert!(chain.append_all(vec![]).is_ok());
+    assert_eq!(3, chain.tip_height());
+}
+
+fn make_branch(parent: &HashedBlock, length: usize, staker_sk: &SecretKey)
+               -> Vec<HashedBlock> {
+    let mut branch: Vec<HashedBlock> = vec![];
+    for _ in range(0, length) {
+        let block = make_block(branch.last().unwrap_or(parent), vec![],
+                               staker_sk);
+        branch.push(block);
+    }
+    branch
+}
+
+#[test]
+fn test_blockchain_try_reorg_longer_fork() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (_, sk3) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let transfer = tx_builder.build().unwrap();
+    let block1 = make_block(&genesis, vec![], &sk1);
+    let block2 = make_block(&block1, vec![transfer.clone()], &sk1);
+    chain.append_all(vec![block1.clone(), block2.clone()]).unwrap();
+    assert_eq!(90, chain.get_balance(&pk1).get_tokens());
+
+    let branch = make_branch(&block1, 2, &sk3);
+    let branch_tip = branch[1].decode_hash().unwrap();
+    assert!(chain.try_reorg(branch).unwrap());
+    assert_eq!(branch_tip, *chain.tip());
+    assert_eq!(3, chain.tip_height());
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(0, chain.get_balance(&pk2).get_tokens());
+    assert!(chain.block_by_hash(&block1.decode_hash().unwrap()).is_some());
+    assert!(chain.block_by_hash(&block2.decode_hash().unwrap()).is_none());
+    assert!(chain.transaction_by_id(&transfer.compute_id()).is_none());
+    assert!(chain.stale_blocks() == &[block2][..]);
+}
+
+#[test]
+fn test_blockchain_try_reorg_shorter_fork() {
+    let (_, sk1) = gen_keypair();
+    let (_, sk2) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    chain.append_all(make_branch(&genesis, 3, &sk1)).unwrap();
+    let tip = chain.tip().clone();
+
+    assert!(!chain.try_reorg(make_branch(&genesis, 2, &sk2)).unwrap());
+    assert!(!chain.try_reorg(make_branch(&genesis, 3, &sk2)).unwrap());
+    assert_eq!(tip, *chain.tip());
+    assert_eq!(3, chain.tip_height());
+    assert!(chain.stale_blocks().is_empty());
+
+    let unknown_parent = make_block(&genesis, vec![], &sk2);
+    assert!(chain.try_reorg(make_branch(&unknown_parent, 4, &sk2)).is_err());
+    assert!(chain.try_reorg(vec![]).is_err());
+    assert_eq!(tip, *chain.tip());
+}
+
+#[test]
+fn test_blockchain_try_reorg_invalid_fork() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    chain.append_all(make_branch(&genesis, 2, &sk1)).unwrap();
+    let tip = chain.tip().clone();
+
+    // The branch is longer, but its last block overspends.
+    let (_, sk2) = gen_keypair();
+    let mut branch = make_branch(&genesis, 2, &sk2);
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 101, 0);
+    let overspend = make_block(&branch[1], vec![tx_builder.build().unwrap()],
+                               &sk1);
+    branch.push(overspend);
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.try_reorg(branch).unwrap_err().kind());
+    assert_eq!(tip, *chain.tip());
+    assert_eq!(2, chain.tip_height());
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+    assert!(chain.stale_blocks().is_empty());
+    assert_eq!(1, chain.stats().get(ErrorKind::DoubleSpend));
+    assert_eq!(1, chain.stats().total());
+}
+
+#[test]
+fn test_blockchain_new_rejects_non_genesis() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let mut block = make_block(&genesis, vec![], &sk);
+    assert!(block.verify().is_ok());
+    assert!(Blockchain::new(block.clone()).is_err());
+
+    block.mut_signed_block().mut_block().set_height(0);
+    block.mut_signed_block().sign(&sk);
+    block.compute_hash();
+    assert!(block.verify().is_ok());
+    assert!(Blockchain::new(block).is_err());
+    assert!(Blockchain::new(genesis).is_ok());
+}
+
+#[test]
+fn test_blockchain_with_genesis_hash() {
+    let (pk, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk, 100);
+    let genesis = builder.build().unwrap();
+    let genesis_hash = genesis.decode_hash().unwrap();
+    let chain =
+        Blockchain::with_genesis_hash(genesis.clone(), &genesis_hash).unwrap();
+    assert_eq!(genesis_hash, *chain.tip());
+
+    let other_genesis = GenesisBuilder::new().build().unwrap();
+    let err = Blockchain::with_genesis_hash(
+        other_genesis.clone(), &genesis_hash).err().unwrap();
+    assert_eq!(ErrorKind::HashMismatch, err.kind());
+    assert!(Blockchain::new(other_genesis).is_ok());
+
+    // The stored hash has to match the contents as well.
+    let mut tampered = genesis.clone();
+    tampered.mut_signed_block().mut_block().set_timestamp(0);
+    assert!(Blockchain::with_genesis_hash(tampered, &genesis_hash).is_err());
+}
+
+#[test]
+fn test_block_work() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    assert_eq!(HashDigest::from_u64(1), block_work(&genesis));
+
+    let mut block = make_block(&genesis, vec![], &sk);
+    block.mine(3).unwrap();
+    assert_eq!(HashDigest::from_u64(8), block_work(&block));
+    block.mut_signed_block().set_difficulty(20);
+    assert_eq!(HashDigest::from_u64(1 << 20), block_work(&block));
+    assert_eq!(HashDigest::from_u64((1 << 20) + 1),
+               chain_work(&[genesis.clone(), block]));
+    assert_eq!(HashDigest::from_u64(0), chain_work(&[]));
+
+    let mut chain = Blockchain::new(genesis).unwrap();
+    assert_eq!(HashDigest::from_u64(1), chain.total_work());
+    let mut block = make_block(chain.blocks().last().unwrap(), vec![], &sk);
+    block.mine(2).unwrap();
+    chain.append(block).unwrap();
+    assert_eq!(HashDigest::from_u64(5), chain.total_work());
+}
+
+#[test]
+fn test_blockchain_try_reorg_heavier_fork() {
+    let (_, sk1) = gen_keypair();
+    let (_, sk2) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    chain.append_all(make_branch(&genesis, 2, &sk1)).unwrap();
+    let tip = chain.tip().clone();
+
+    // Same length and the same work.
+    assert!(!chain.try_reorg(make_branch(&genesis, 2, &sk2)).unwrap());
+    assert_eq!(tip, *chain.tip());
+
+    // Same length, but the second block is mined.
+    let mut heavier = make_branch(&genesis, 1, &sk2);
+    let mut block = make_block(&heavier[0], vec![], &sk2);
+    block.mine(4).unwrap();
+    heavier.push(block);
+    let heavier_tip = heavier[1].decode_hash().unwrap();
+    assert!(chain.try_reorg(heavier).unwrap());
+    assert_eq!(heavier_tip, *chain.tip());
+    assert_eq!(HashDigest::from_u64(1 + 1 + 16), chain.total_work());
+
+    // Shorter, but heavier still.
+    let mut block = make_block(&genesis, vec![], &sk1);
+    block.mine(5).unwrap();
+    let block_hash = block.decode_hash().unwrap();
+    assert!(chain.try_reorg(vec![block]).unwrap());
+    assert_eq!(block_hash, *chain.tip());
+    assert_eq!(1, chain.tip_height());
+    assert_eq!(HashDigest::from_u64(1 + 32), chain.total_work());
+}
+
+#[test]
+fn test_blockchain_find_blocks_by_hash_prefix() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    chain.append_all(make_branch(&genesis, 8, &sk)).unwrap();
+
+    let hashes: Vec<String> =
+        chain.blocks().map(|block| block.get_hash().to_hex()).collect();
+    for (index, block_hash) in hashes.iter().enumerate() {
+        let found =
+            chain.find_blocks_by_hash_prefix(&block_hash[..12]).unwrap();
+        assert_eq!(1, found.len());
+        assert_eq!(index as u32, found[0].get_height());
+        let upper = block_hash[..12].to_ascii_uppercase();
+        assert_eq!(1, chain.find_blocks_by_hash_prefix(&upper).unwrap().len());
+    }
+
+    // Every hash starts with one of the 16 hex digits.
+    let mut total = 0;
+    for digit in "0123456789abcdef".chars() {
+        let prefix = digit.to_string();
+        let found = chain.find_blocks_by_hash_prefix(&prefix).unwrap();
+        let expected = hashes.iter()
+            .filter(|block_hash| block_hash.starts_with(&prefix)).count();
+        assert_eq!(expected, found.len());
+        total += found.len();
+    }
+    assert_eq!(9, total);
+    assert_eq!(9, chain.find_blocks_by_hash_prefix("").unwrap().len());
+
+    assert!(chain.find_blocks_by_hash_prefix("12g4").is_err());
+    assert!(chain.find_blocks_by_hash_prefix(" ab").is_err());
+}
+
+#[test]
+fn test_blockchain_max_block_size() {
+    use protobuf::Message;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(DEFAULT_MAX_BLOCK_SIZE, chain.max_block_size());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let block = make_block(&genesis, vec![tx_builder.build().unwrap()], &sk1);
+    let size = block.write_to_bytes().unwrap().len();
+
+    chain.set_max_block_size(size - 1);
+    assert!(chain.append(block.clone()).is_err());
+    assert_eq!(0, chain.tip_height());
+
+    chain.set_max_block_size(size);
+    chain.append(block.clone()).unwrap();
+    assert_eq!(1, chain.tip_height());
+
+    // Lowering the limit does not stop a reorg from replaying the blocks
+    // already accepted, but does apply to the branch.
+    let (_, sk3) = gen_keypair();
+    let empty_size = make_block(&genesis, vec![], &sk1)
+        .write_to_bytes().unwrap().len();
+    chain.set_max_block_size(empty_size);
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 1);
+    let mut oversized = make_branch(&block, 1, &sk3);
+    let transfer_block = make_block(&oversized[0],
+                                    vec![tx_builder.build().unwrap()], &sk3);
+    oversized.push(transfer_block);
+    assert!(chain.try_reorg(oversized).is_err());
+    assert_eq!(1, chain.tip_height());
+    assert!(chain.try_reorg(make_branch(&block, 2, &sk3)).unwrap());
+    assert_eq!(3, chain.tip_height());
+    assert_eq!(empty_size, chain.max_block_size());
+}
+
+#[test]
+fn test_blockchain_max_tx_size() {
+    use protobuf::Message;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(DEFAULT_MAX_TX_SIZE, chain.max_tx_size());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let transaction = tx_builder.build().unwrap();
+    let size = transaction.write_to_bytes().unwrap().len();
+    let block = make_block(&genesis, vec![transaction], &sk1);
+
+    chain.set_max_tx_size(size - 1);
+    assert!(chain.append(block.clone()).is_err());
+    assert_eq!(0, chain.tip_height());
+
+    chain.set_max_tx_size(size);
+    chain.append(block).unwrap();
+    assert_eq!(1, chain.tip_height());
+}
+
+#[test]
+fn test_blockchain_would_accept() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let chain = Blockchain::new(genesis.clone()).unwrap();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 60, 0);
+    let transaction = tx_builder.build().unwrap();
+    assert!(chain.would_accept(&transaction).is_ok());
+    assert!(chain.would_accept(&transaction).is_ok());
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(0, chain.get_balance(&pk1).get_op_index());
+    assert_eq!(0, chain.get_balance(&pk2).get_tokens());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 60, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 60, 1);
+    let overspend = tx_builder.build().unwrap();
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.would_accept(&overspend).unwrap_err().kind());
+    assert_eq!(100, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(0, chain.get_balance(&pk2).get_tokens());
+    assert_eq!(genesis.decode_hash().unwrap(), *chain.tip());
+
+    let mut unsigned = transaction.clone();
+    unsigned.clear_signatures();
+    assert!(chain.would_accept(&unsigned).is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 1);
+    assert!(chain.would_accept(&tx_builder.build().unwrap()).is_err());
+}
+
+#[test]
+fn test_verify_chain_with_checkpoints() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let mut blocks = vec![builder.build().unwrap()];
+    for height in range(1, 5) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, height - 1);
+        let mut transaction = tx_builder.build().unwrap();
+        if height == 2 {
+            // Sealed into the block, so only the signature checks catch it.
+            transaction.mut_signatures()[0].mut_payload()[0] ^= 1;
+        }
+        let block = make_block(&blocks[blocks.len() - 1], vec![transaction],
+                               &sk1);
+        blocks.push(block);
+    }
+    assert!(verify_chain(&blocks).is_err());
+
+    let mut checkpoints = BTreeMap::new();
+    checkpoints.insert(3, blocks[3].decode_hash().unwrap());
+    assert!(verify_chain_with_checkpoints(&blocks, &checkpoints).is_ok());
+    assert!(verify_chain_with_checkpoints(&blocks[..3], &checkpoints).is_err());
+    assert!(verify_chain_with_checkpoints(&blocks[3..], &checkpoints).is_ok());
+
+    checkpoints.insert(3, blocks[2].decode_hash().unwrap());
+    let err = verify_chain_with_checkpoints(&blocks, &checkpoints).unwrap_err();
+    assert_eq!(ErrorKind::HashMismatch, err.kind());
+    assert!(err.description().contains("Block 3"));
+
+    let mut checkpoints = BTreeMap::new();
+    checkpoints.insert(1, blocks[1].decode_hash().unwrap());
+    assert!(verify_chain_with_checkpoints(&blocks, &checkpoints).is_err());
+}
+
+#[test]
+fn test_blockchain_checkpoints() {
+    let (_, sk1) = gen_keypair();
+    let (_, sk2) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let branch = make_branch(&genesis, 3, &sk1);
+    let other = make_branch(&genesis, 3, &sk2);
+
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    chain.set_checkpoint(2, branch[1].decode_hash().unwrap());
+    assert_eq!(ErrorKind::HashMismatch,
+               chain.append_all(other.clone()).unwrap_err().kind());
+    assert_eq!(0, chain.tip_height());
+    chain.append_all(branch.clone()).unwrap();
+    assert_eq!(3, chain.tip_height());
+
+    let mut blocks = vec![genesis.clone()];
+    blocks.push_all(&branch);
+    assert!(chain.verify_blocks(&blocks).is_ok());
+    let mut tampered = blocks.clone();
+    tampered[2] = other[1].clone();
+    assert!(chain.verify_blocks(&tampered).is_err());
+
+    // Nor can a reorg replace the checkpointed block.
+    let longer = make_branch(&genesis, 4, &sk2);
+    assert!(chain.try_reorg(longer).is_err());
+    assert_eq!(branch[2].decode_hash().unwrap(), *chain.tip());
+}
+
+#[test]
+fn test_blockchain_prune() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+
+    let mut blocks = vec![];
+    let mut tx_ids = vec![];
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 20, op_index);
+        let transaction = tx_builder.build().unwrap();
+        tx_ids.push(transaction.compute_id());
+        let parent = blocks.last().unwrap_or(&genesis).clone();
+        blocks.push(make_block(&parent, vec![transaction], &sk1));
+    }
+    chain.append_all(blocks.clone()).unwrap();
+    let tip = chain.tip().clone();
+    // Pruned blocks below a checkpoint are still only checked by header.
+    chain.set_checkpoint(1, blocks[0].decode_hash().unwrap());
+
+    chain.prune(2);
+    assert_eq!(2, chain.pruned_height());
+    assert_eq!(tip, *chain.tip());
+    assert_eq!(4, chain.blocks().count());
+    assert_eq!(1, chain.transactions().count());
+    assert!(chain.transaction_by_id(&tx_ids[0]).is_none());
+    assert!(chain.transaction_by_id(&tx_ids[2]).is_some());
+    assert_eq!(40, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(60, chain.get_balance(&pk2).get_tokens());
+
+    // The pruned blocks keep their hashes but only verify by header.
+    let pruned =
+        chain.block_by_hash(&blocks[0].decode_hash().unwrap()).unwrap();
+    assert_eq!(0, pruned.get_block().get_transactions().len());
+    assert!(pruned.verify().is_err());
+    assert!(pruned.verify_header().is_ok());
+    assert!(chain.verify().is_ok());
+    assert!(chain.blocks().last().unwrap().verify().is_ok());
+    let pruned_blocks =
+        chain.blocks().map(|block| block.clone()).collect::<Vec<_>>();
+    assert!(verify_chain(&pruned_blocks).is_err());
+
+    // Pruning less than before is a no-op.
+    chain.prune(1);
+    assert_eq!(2, chain.pruned_height());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 40, 3);
+    chain.append(make_block(&blocks[2], vec![tx_builder.build().unwrap()],
+                            &sk1)).unwrap();
+    assert_eq!(0, chain.get_balance(&pk1).get_tokens());
+    assert!(chain.verify().is_ok());
+    assert!(chain.try_reorg(make_branch(&blocks[2], 2, &sk1)).is_err());
+
+    chain.prune(100);
+    assert_eq!(5, chain.pruned_height());
+    assert_eq!(0, chain.transactions().count());
+    assert!(chain.verify().is_ok());
+}
+
+#[test]
+fn test_blockchain_require_coinbase() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let (miner_pk, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    chain.set_require_coinbase(true);
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 10, 2, 0);
+    let transaction = tx_builder.build().unwrap();
+    let without = make_block(&genesis, vec![transaction.clone()], &sk1);
+    assert!(without.verify().is_ok());
+    assert_eq!(ErrorKind::Conservation,
+               chain.append(without).unwrap_err().kind());
+
+    let coinbase = make_coinbase(&miner_pk, BLOCK_REWARD + 2, 1);
+    assert!(chain.would_accept(&coinbase).is_err());
+    let block = make_block(&genesis, vec![coinbase, transaction], &sk1);
+    chain.append(block.clone()).unwrap();
+    assert_eq!(BLOCK_REWARD + 2, chain.get_balance(&miner_pk).get_tokens());
+    // The staker, pk1, is not paid the fees again.
+    assert_eq!(88, chain.get_balance(&pk1).get_tokens());
+    assert_eq!(10, chain.get_balance(&pk2).get_tokens());
+
+    let over_reward = make_block(
+        &block, vec![make_coinbase(&miner_pk, BLOCK_REWARD + 1, 2)], &sk1);
+    assert!(chain.append(over_reward).is_err());
+    chain.append(make_block(
+        &block, vec![make_coinbase(&miner_pk, BLOCK_REWARD, 2)], &sk1)).unwrap();
+    assert_eq!(2 * BLOCK_REWARD + 2,
+               chain.get_balance(&miner_pk).get_tokens());
+}
+
+#[test]
+fn test_blockchain_stats() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(0, chain.stats().total());
+
+    let mut tampered = make_block(&genesis, vec![], &sk1);
+    tampered.mut_signed_block().mut_block().set_timestamp(
+        genesis.get_block().get_timestamp() + 1);
+    assert!(chain.append(tampered.clone()).is_err());
+    assert_eq!(1, chain.stats().get(ErrorKind::HashMismatch));
+
+    tampered.compute_hash();
+    assert!(chain.append(tampered).is_err());
+    assert_eq!(1, chain.stats().get(ErrorKind::SignatureInvalid));
+
+    let mut short_hash = make_block(&genesis, vec![], &sk1);
+    short_hash.mut_hash().pop();
+    assert!(chain.append(short_hash).is_err());
+    assert_eq!(1, chain.stats().get(ErrorKind::Decode));
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 101, 0);
+    let overspend =
+        make_block(&genesis, vec![tx_builder.build().unwrap()], &sk1);
+    assert!(chain.append(overspend).is_err());
+    assert_eq!(1, chain.stats().get(ErrorKind::DoubleSpend));
+
+    let over_reward = make_block(
+        &genesis, vec![make_coinbase(&pk2, BLOCK_REWARD + 1, 1)], &sk1);
+    assert!(chain.append(over_reward).is_err());
+    assert_eq!(1, chain.stats().get(ErrorKind::Conservation));
+
+    let block1 = make_block(&genesis, vec![], &sk1);
+    assert!(chain.append(make_block(&block1, vec![], &sk1)).is_err());
+    assert_eq!(1, chain.stats().get(ErrorKind::Other));
+    assert_eq!(6, chain.stats().total());
+
+    // Accepted blocks and reorganisations don't reset the counters.
+    chain.append(block1.clone()).unwrap();
+    assert!(chain.try_reorg(make_branch(&genesis, 2, &sk1)).unwrap());
+    assert_eq!(6, chain.stats().total());
+}
+
+#[test]
+fn test_shared_chain_concurrent_reads() {
+    let (_, sk) = gen_keypair();
+    let genesis = GenesisBuilder::new().build().unwrap();
+    let genesis_hash = genesis.decode_hash().unwrap();
+    let branch = make_branch(&genesis, 20, &sk);
+    let last_hash = branch[19].decode_hash().unwrap();
+    let shared = SharedChain::new(Blockchain::new(genesis).unwrap());
+
+    let readers: Vec<_> = range(0, 4).map(|_| {
+        let shared = shared.clone();
+        let genesis_hash = genesis_hash.clone();
+        thread::scoped(move || {
+            let mut last_height = 0;
+            for _ in range(0, 200) {
+                let (height, tip_height) = shared.with_chain(|chain| {
+                    let tip = chain.block_by_hash(chain.tip()).unwrap();
+                    (tip.get_height(), chain.tip_height())
+                });
+                assert_eq!(height, tip_height);
+                assert!(height >= last_height);
+                last_height = height;
+                assert!(shared.block_by_hash(&genesis_hash).is_some());
+            }
+        })
+    }).collect();
+    {
+        let shared = shared.clone();
+        let writer = thread::scoped(move || {
+            for block in branch.into_iter() {
+                shared.append(block).unwrap();
+            }
+        });
+        writer.join();
+    }
+    for reader in readers.into_iter() {
+        reader.join();
+    }
+    assert_eq!(20, shared.tip_height());
+    assert_eq!(last_hash, shared.tip());
+}
+
+#[test]
+fn test_blockchain_export_import() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    let mut parent = genesis.clone();
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        let block =
+            make_block(&parent, vec![tx_builder.build().unwrap()], &sk1);
+        chain.append(block.clone()).unwrap();
+        parent = block;
+    }
+
+    let mut exported = vec![];
+    chain.export_to(&mut exported).unwrap();
+    let imported = Blockchain::import_from(&exported[..]).unwrap();
+    assert_eq!(chain.tip(), imported.tip());
+    assert_eq!(3, imported.tip_height());
+    assert_eq!(4, imported.blocks().count());
+    assert_eq!(70, imported.get_balance(&pk1).get_tokens());
+    assert_eq!(30, imported.get_balance(&pk2).get_tokens());
+    assert!(imported.verify().is_ok());
+
+    assert_eq!(ErrorKind::Decode,
+               Blockchain::import_from(&b""[..]).err().unwrap().kind());
+    // Cut in the middle of the last block.
+    let truncated = &exported[..exported.len() - 5];
+    assert_eq!(ErrorKind::Decode,
+               Blockchain::import_from(truncated).err().unwrap().kind());
+
+    // Blocks which decode but don't form a valid chain, here the second
+    // block written twice.
+    let mut writer = BlockFileWriter::new(vec![]);
+    for block in [&genesis, chain.blocks().nth(1).unwrap(),
+                  chain.blocks().nth(1).unwrap()].iter() {
+        writer.write_block(*block).unwrap();
+    }
+    assert!(Blockchain::import_from(&writer.into_inner()[..]).is_err());
+
+    chain.prune(1);
+    assert!(chain.export_to(vec![]).is_err());
+}
+
+#[test]
+fn test_blockchain_balance_of() {
+    // What balance_of avoids: replaying every transfer in the chain.
+    fn scan_balance(chain: &Blockchain, public_key: &PublicKey) -> u64 {
+        let mut balance = 0u64;
+        for block in chain.blocks() {
+            for tx in block.get_block().get_transactions().iter() {
+                for transfer in tx.get_commit().get_transfers().iter() {
+                    if transfer.get_destination_pk() == public_key.as_slice() {
+                        balance += transfer.get_tokens();
+                    }
+                    // The genesis mints its transfers.
+                    if block.get_height() > 0 &&
+                        transfer.get_source_pk() == public_key.as_slice()
+                    {
+                        balance -= transfer.get_tokens() + transfer.get_fee();
+                    }
+                }
+            }
+        }
+        balance
+    }
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (pk3, sk3) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100).add_transfer(pk2.clone(), 50);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    let keys = [pk1.clone(), pk2.clone(), pk3.clone()];
+
+    let mut blocks = vec![genesis];
+    let transfers = [(&sk1, &pk1, &pk2, 30, 0), (&sk2, &pk2, &pk3, 60, 0),
+                     (&sk3, &pk3, &pk1, 10, 0), (&sk1, &pk1, &pk3, 5, 1)];
+    for &(sk, source, destination, tokens, op_index) in transfers.iter() {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(sk, source, destination, tokens, op_index);
+        let block = make_block(blocks.last().unwrap(),
+                               vec![tx_builder.build().unwrap()], &sk1);
+        chain.append(block.clone()).unwrap();
+        blocks.push(block);
+        for public_key in keys.iter() {
+            assert_eq!(scan_balance(&chain, public_key),
+                       chain.balance_of(public_key));
+        }
+    }
+    assert_eq!(75, chain.balance_of(&pk1));
+    assert_eq!(20, chain.balance_of(&pk2));
+    assert_eq!(55, chain.balance_of(&pk3));
+
+    // Fork after the second transfer, with a different third one.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk3, &pk3, &pk2, 40, 0);
+    let fork = make_block(&blocks[2], vec![tx_builder.build().unwrap()], &sk1);
+    let mut branch = vec![fork.clone()];
+    branch.extend(make_branch(&fork, 2, &sk1).into_iter());
+    assert!(chain.try_reorg(branch).unwrap());
+    for public_key in keys.iter() {
+        assert_eq!(scan_balance(&chain, public_key),
+                   chain.balance_of(public_key));
+    }
+    assert_eq!(70, chain.balance_of(&pk1));
+    assert_eq!(60, chain.balance_of(&pk2));
+    assert_eq!(20, chain.balance_of(&pk3));
+}
+
+#[test]
+fn test_blockchain_seal_and_append() {
+    use time::Timespec;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        let mut block = HashedBlock::new();
+        block.mut_signed_block().mut_block().mut_transactions()
+            .push(tx_builder.build().unwrap());
+        let tip = chain.tip().clone();
+        chain.seal_and_append(&mut block, &sk1).unwrap();
+        assert_eq!(tip, block.decode_previous().unwrap());
+        assert_eq!(block.decode_hash().unwrap(), *chain.tip());
+        assert_eq!(op_index + 1, chain.tip_height());
+    }
+    assert_eq!(70, chain.balance_of(&pk1));
+    assert_eq!(30, chain.balance_of(&pk2));
+    let blocks = chain.blocks().map(|block| block.clone()).collect::<Vec<_>>();
+    assert!(verify_chain(&blocks).is_ok());
+
+    // Still verified: the same transfer can't go in twice.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 2);
+    let mut replay = HashedBlock::new();
+    replay.mut_signed_block().mut_block().mut_transactions()
+        .push(tx_builder.build().unwrap());
+    replay.mine(4).unwrap();
+    let before = replay.clone();
+    assert!(chain.seal_and_append(&mut replay, &sk1).is_err());
+    assert_eq!(3, chain.tip_height());
+    assert!(before == replay);
+
+    // A block mined before it was sealed has to be mined again.
+    let mut mined = HashedBlock::new();
+    mined.mine(4).unwrap();
+    chain.seal_and_append(&mut mined, &sk1).unwrap();
+    assert_eq!(0, mined.get_signed_block().get_difficulty());
+    assert_eq!(mined.decode_hash().unwrap(), *chain.tip());
+
+    // The block is stamped by the given clock, unless it is behind the tip.
+    struct FixedClock(i64);
+    impl Clock for FixedClock {
+        fn now(&self) -> Timespec { Timespec::new(self.0, 0) }
+    }
+    let tip_timestamp = mined.get_block().get_timestamp();
+    let mut later = HashedBlock::new();
+    chain.seal_and_append_with_clock(
+        &mut later, &sk1, &FixedClock(tip_timestamp + 60)).unwrap();
+    assert_eq!(tip_timestamp + 60, later.get_block().get_timestamp());
+    let mut earlier = HashedBlock::new();
+    chain.seal_and_append_with_clock(
+        &mut earlier, &sk1, &FixedClock(tip_timestamp)).unwrap();
+    assert_eq!(tip_timestamp + 60, earlier.get_block().get_timestamp());
+}
+
+#[test]
+fn test_blockchain_sync_patches() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut leading = Blockchain::new(genesis.clone()).unwrap();
+    let mut lagging = Blockchain::new(genesis.clone()).unwrap();
+
+    let mut parent = genesis.clone();
+    for op_index in range(0, 4) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        let block =
+            make_block(&parent, vec![tx_builder.build().unwrap()], &sk1);
+        leading.append(block.clone()).unwrap();
+        if op_index == 0 { lagging.append(block.clone()).unwrap(); }
+        parent = block;
+    }
+
+    let patches = compute_sync_patches(lagging.tip(), &leading).unwrap();
+    assert_eq!(3, patches.len());
+    assert!(patches.iter().all(|patch| patch.get_added().len() == 1));
+    lagging.apply_sync_patches(patches).unwrap();
+    assert_eq!(leading.tip(), lagging.tip());
+    assert_eq!(4, lagging.tip_height());
+    assert_eq!(60, lagging.balance_of(&pk1));
+    assert!(compute_sync_patches(lagging.tip(), &leading).unwrap().is_empty());
+
+    // Patches for a different tip fail to apply and leave the chain as is.
+    let patches = compute_sync_patches(&genesis.decode_hash().unwrap(),
+                                       &leading).unwrap();
+    assert!(lagging.apply_sync_patches(patches).is_err());
+    assert_eq!(leading.tip(), lagging.tip());
+    assert!(compute_sync_patches(&HashDigest::from_u64(1), &leading).is_err());
+}
+
+#[test]
+fn test_blockchain_sync_patches_pruned() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut leading = Blockchain::new(genesis.clone()).unwrap();
+    let mut lagging = Blockchain::new(genesis.clone()).unwrap();
+    let mut parent = genesis.clone();
+    for op_index in range(0, 3) {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        let block =
+            make_block(&parent, vec![tx_builder.build().unwrap()], &sk1);
+        leading.append(block.clone()).unwrap();
+        if op_index == 0 { lagging.append(block.clone()).unwrap(); }
+        parent = block;
+    }
+    let mut also_lagging = Blockchain::new(genesis.clone()).unwrap();
+    also_lagging.append(lagging.blocks().nth(1).unwrap().clone()).unwrap();
+
+    // Only blocks below the lagging tip are pruned, so the patches are
+    // diffed against the transactions it has.
+    leading.prune(1);
+    let patches = compute_sync_patches(lagging.tip(), &leading).unwrap();
+    lagging.apply_sync_patches(patches).unwrap();
+    assert_eq!(leading.tip(), lagging.tip());
+    assert_eq!(70, lagging.balance_of(&pk1));
+
+    // Now the lagging tip itself is pruned.
+    leading.prune(2);
+    assert!(compute_sync_patches(also_lagging.tip(), &leading).is_err());
+    assert!(compute_sync_patches(lagging.tip(), &leading).unwrap().is_empty());
+}
+
+#[test]
+fn test_blockchain_next_op_index() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut builder = GenesisBuilder::new();
+    builder.add_transfer(pk1.clone(), 100);
+    let genesis = builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis.clone()).unwrap();
+    assert_eq!(0, chain.next_op_index(&pk1));
+    assert_eq!(0, chain.next_op_index(&pk2));
+
+    let make_transfer = |op_index| {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        tx_builder.build().unwrap()
+    };
+    let mut transfers = vec![];
+    let mut parent = genesis.clone();
+    for op_index in range(0, 3) {
+        let transfer = make_transfer(op_index);
+        assert!(chain.would_accept(&transfer).is_ok());
+        let block = make_block(&parent, vec![transfer.clone()], &sk1);
+        chain.append(block.clone()).unwrap();
+        assert_eq!(op_index + 1, chain.next_op_index(&pk1));
+        transfers.push(transfer);
+        parent = block;
+    }
+    // Receiving tokens doesn't use up the destination's op indices.
+    assert_eq!(0, chain.next_op_index(&pk2));
+
+    // Stale: each of the signed transfers replayed in a new block.
+    for transfer in transfers.into_iter() {
+        assert_eq!(ErrorKind::DoubleSpend,
+                   chain.would_accept(&transfer).unwrap_err().kind());
+        let replay = make_block(&parent, vec![transfer], &sk1);
+        assert_eq!(ErrorKind::DoubleSpend,
+                   chain.append(replay).unwrap_err().kind());
+    }
+    // Skipping ahead is rejected too.
+    let skipped = make_block(&parent, vec![make_transfer(4)], &sk1);
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.append(skipped).unwrap_err().kind());
+    assert_eq!(3, chain.next_op_index(&pk1));
+    assert_eq!(70, chain.balance_of(&pk1));
+
+    // A transfer to oneself takes an op_index like any other.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk1, 70, 3);
+    let self_transfer = tx_builder.build().unwrap();
+    let block = make_block(&parent, vec![self_transfer.clone()], &sk1);
+    chain.append(block.clone()).unwrap();
+    assert_eq!(4, chain.next_op_index(&pk1));
+    assert_eq!(70, chain.balance_of(&pk1));
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.would_accept(&self_transfer).unwrap_err().kind());
+    let replay = make_block(&block, vec![self_transfer], &sk1);
+    assert_eq!(ErrorKind::DoubleSpend,
+               chain.append(replay).unwrap_err().kind());
+    assert_eq!(70, chain.balance_of(&pk1));
+}
@@ -0,0 +1,92 @@
+use error::{ErrorKind, IroncError, IroncResult};
+
+// Varints store 7 bits per byte, least significant group first, with the top
+// bit set on every byte but the last. A u64 takes between 1 and 10 bytes.
+pub const MAX_VARINT_BYTES: usize = 10;
+
+pub fn encode_varint(mut value: u64, output: &mut Vec<u8>) {
+    while value >= 0x80 {
+        output.push((value & 0x7f) as u8 | 0x80);
+        value = value >> 7;
+    }
+    output.push(value as u8);
+}
+
+// Returns the decoded value and the number of bytes it took up.
+pub fn decode_varint(bytes: &[u8]) -> IroncResult<(u64, usize)> {
+    let mut value = 0u64;
+    for (index, &byte) in bytes.iter().take(MAX_VARINT_BYTES).enumerate() {
+        let shift = 7 * index;
+        // The last byte has room for one bit and no continuation, so a longer
+        // varint is rejected here too.
+        if index == MAX_VARINT_BYTES - 1 && byte > 1 {
+            return Err(IroncError::with_kind(
+                ErrorKind::Decode, "Varint overflows a u64."));
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+    Err(IroncError::with_kind(ErrorKind::Decode, "Truncated varint."))
+}
+
+/*****  Tests  *****/
+
+use std::u64;
+
+use sodiumoxide::randombytes::randombytes;
+
+fn round_trip(value: u64) -> usize {
+    let mut bytes = vec![];
+    encode_varint(value, &mut bytes);
+    assert_eq!((value, bytes.len()), decode_varint(&bytes).unwrap());
+    bytes.len()
+}
+
+#[test]
+fn test_varint_boundaries() {
+    assert_eq!(1, round_trip(0));
+    assert_eq!(1, round_trip(1));
+    assert_eq!(1, round_trip(0x7f));
+    assert_eq!(2, round_trip(0x80));
+    assert_eq!(2, round_trip(0x3fff));
+    assert_eq!(3, round_trip(0x4000));
+    assert_eq!(9, round_trip(u64::MAX >> 1));
+    assert_eq!(MAX_VARINT_BYTES, round_trip(u64::MAX));
+
+    let mut zero = vec![];
+    encode_varint(0, &mut zero);
+    assert_eq!(vec![0u8], zero);
+}
+
+#[test]
+fn test_varint_random_amounts() {
+    for chunk in randombytes(8 * 32).chunks(8) {
+        let mut value = 0u64;
+        for &byte in chunk.iter() {
+            value = (value << 8) | byte as u64;
+        }
+        // Also cover the shorter encodings, which the typical amounts use.
+        round_trip(value);
+        round_trip(value >> (value % 64) as usize);
+    }
+}
+
+#[test]
+fn test_varint_decode_trailing_and_invalid() {
+    let mut bytes = vec![];
+    encode_varint(300, &mut bytes);
+    encode_varint(7, &mut bytes);
+    assert_eq!((300, 2), decode_varint(&bytes).unwrap());
+    assert_eq!((7, 1), decode_varint(&bytes[2..]).unwrap());
+
+    assert_eq!(ErrorKind::Decode, decode_varint(&[]).unwrap_err().kind());
+    assert!(decode_varint(&[0x80]).is_err());
+    assert!(decode_varint(&[0xff; MAX_VARINT_BYTES]).is_err());
+    assert!(decode_varint(&[0xff; MAX_VARINT_BYTES + 1]).is_err());
+
+    let mut overflow = vec![0xffu8; MAX_VARINT_BYTES - 1];
+    overflow.push(0x02);
+    assert!(decode_varint(&overflow).is_err());
+}
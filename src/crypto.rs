@@ -2,15 +2,19 @@ use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 use std::slice::bytes::copy_memory;
 
 use protobuf::MessageStatic;
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
 use rustc_serialize::base64::{self, ToBase64};
-use sodiumoxide::crypto::hash::sha512::{self, HASHBYTES};
+use rustc_serialize::hex::{FromHex, ToHex};
+pub use sodiumoxide::crypto::hash::sha512::HASHBYTES;
+use sodiumoxide::crypto::hash::sha512;
 use sodiumoxide::crypto::sign::ed25519::{
-    self, PUBLICKEYBYTES, SECRETKEYBYTES, SIGNATUREBYTES};
+    self, PUBLICKEYBYTES, SECRETKEYBYTES, SEEDBYTES, SIGNATUREBYTES};
 
-use error::{IroncError, IroncResult};
+use error::{ErrorKind, IroncError, IroncResult};
 
 // HashDigest:
 
@@ -21,7 +25,7 @@ impl HashDigest {
 
     pub fn from_slice(bytes: &[u8]) -> IroncResult<HashDigest> {
         if bytes.len() != HASHBYTES {
-            Err(IroncError::new(&format!(
+            Err(IroncError::with_kind(ErrorKind::Decode, &format!(
                 "Invalid length for a hash {} != {} (required).",
                 bytes.len(), HASHBYTES)))
         } else {
@@ -31,6 +35,31 @@ impl HashDigest {
         }
     }
 
+    pub fn from_hex(hex_str: &str) -> IroncResult<HashDigest> {
+        if hex_str.len() != 2 * HASHBYTES {
+            return Err(IroncError::with_kind(ErrorKind::Decode, &format!(
+                "Invalid length for a hex encoded hash {} != {} (required).",
+                hex_str.len(), 2 * HASHBYTES)));
+        }
+        match hex_str.from_hex() {
+            Ok(bytes) => HashDigest::from_slice(&bytes),
+            Err(err) => Err(IroncError::with_kind(ErrorKind::Decode, &format!(
+                "Could not decode \"{}\" as a hash: {}", hex_str, err)))
+        }
+    }
+
+    pub fn to_base64(&self) -> String { self.0.to_base64(base64::STANDARD) }
+
+    pub fn to_hex(&self) -> String { self.0.to_hex() }
+
+    // The all zero digest, which is e.g. the previous hash of a genesis block.
+    pub fn zero() -> HashDigest { HashDigest([0; HASHBYTES]) }
+
+    pub fn is_zero(&self) -> bool { self.0.iter().all(|&byte| byte == 0) }
+
+    // The digest of the empty input.
+    pub fn of_empty() -> HashDigest { hash(&[]) }
+
     pub fn from_u64(mut value: u64) -> HashDigest {
         let mut proof_hash = HashDigest([0; HASHBYTES]);
         let mut index = 0us;
@@ -42,6 +71,34 @@ impl HashDigest {
         proof_hash
     }
 
+    // Constant time comparison, for checking hashes received from others.
+    pub fn ct_eq(&self, other: &HashDigest) -> bool {
+        self.ct_eq_slice(&other.0)
+    }
+
+    // As ct_eq, but against raw bytes, e.g. a hash field of a message. Bytes
+    // of the wrong length are never equal.
+    pub fn ct_eq_slice(&self, bytes: &[u8]) -> bool {
+        if bytes.len() != HASHBYTES {
+            return false;
+        }
+        let mut difference = 0u8;
+        for (left, right) in self.0.iter().zip(bytes.iter()) {
+            difference |= left ^ right;
+        }
+        difference == 0
+    }
+
+    // Counted from the most significant end, i.e. from the last byte.
+    pub fn leading_zero_bits(&self) -> u32 {
+        let mut zero_bits = 0u32;
+        for byte in self.0.iter().rev() {
+            zero_bits += byte.leading_zeros() as u32;
+            if *byte != 0 { break; }
+        }
+        zero_bits
+    }
+
     pub fn multiply_u8_in_place(&mut self, other: u8) {
         let mut quot = 0u16;
         for index in range(0, HASHBYTES) {
@@ -105,7 +162,21 @@ impl fmt::Debug for HashDigest {
 
 impl fmt::Display for HashDigest {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "{}", self.as_slice().to_base64(base64::STANDARD))
+        write!(formatter, "{}", self.to_hex())
+    }
+}
+
+impl Encodable for HashDigest {
+    fn encode<S: Encoder>(&self, encoder: &mut S) -> Result<(), S::Error> {
+        encoder.emit_str(&self.to_hex())
+    }
+}
+
+impl Decodable for HashDigest {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<HashDigest, D::Error> {
+        let hex_str = try!(decoder.read_str());
+        HashDigest::from_hex(&hex_str).map_err(
+            |err| decoder.error(&format!("{}", err)))
     }
 }
 
@@ -123,7 +194,8 @@ impl PublicKey {
 
     pub fn from_slice(bytes: &[u8]) -> IroncResult<PublicKey> {
         if bytes.len() != PUBLICKEYBYTES {
-            Err(IroncError::new(
+            Err(IroncError::with_kind(
+                ErrorKind::Decode,
                 &format!("Invalid public key length {} != {} (required)",
                          bytes.len(), PUBLICKEYBYTES)))
         } else {
@@ -140,6 +212,27 @@ impl fmt::Display for PublicKey {
     }
 }
 
+impl Encodable for PublicKey {
+    fn encode<S: Encoder>(&self, encoder: &mut S) -> Result<(), S::Error> {
+        encoder.emit_str(&self.as_slice().to_hex())
+    }
+}
+
+impl Decodable for PublicKey {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<PublicKey, D::Error> {
+        let bytes = try!(decode_hex_bytes(decoder, "public key"));
+        PublicKey::from_slice(&bytes).map_err(
+            |err| decoder.error(&format!("{}", err)))
+    }
+}
+
+fn decode_hex_bytes<D: Decoder>(decoder: &mut D, what: &str)
+                                -> Result<Vec<u8>, D::Error> {
+    let hex_str = try!(decoder.read_str());
+    hex_str.from_hex().map_err(|err| decoder.error(&format!(
+        "Could not decode \"{}\" as a {}: {}", hex_str, what, err)))
+}
+
 // SecretKey:
 
 pub struct SecretKey(pub [u8; SECRETKEYBYTES]);
@@ -149,7 +242,8 @@ impl SecretKey {
 
     pub fn from_slice(bytes: &[u8]) -> IroncResult<SecretKey> {
         if bytes.len() != SECRETKEYBYTES {
-            Err(IroncError::new(
+            Err(IroncError::with_kind(
+                ErrorKind::Decode,
                 &format!("Invalid secret key length {} != {} (required)",
                          bytes.len(), SECRETKEYBYTES)))
         } else {
@@ -158,6 +252,13 @@ impl SecretKey {
             Ok(secret_key)
         }
     }
+
+    // An ed25519 secret key embeds its public key in the last
+    // PUBLICKEYBYTES bytes.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_slice(&self.0[SECRETKEYBYTES - PUBLICKEYBYTES..])
+            .unwrap()
+    }
 }
 
 impl Drop for SecretKey {
@@ -192,11 +293,51 @@ impl fmt::Display for SecretKey {
     }
 }
 
+// Only with the serialize-secret-key feature, so that secret keys aren't
+// written out to config files or logs by accident.
+#[cfg(feature = "serialize-secret-key")]
+impl Encodable for SecretKey {
+    fn encode<S: Encoder>(&self, encoder: &mut S) -> Result<(), S::Error> {
+        encoder.emit_str(&self.as_slice().to_hex())
+    }
+}
+
+#[cfg(feature = "serialize-secret-key")]
+impl Decodable for SecretKey {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<SecretKey, D::Error> {
+        let bytes = try!(decode_hex_bytes(decoder, "secret key"));
+        SecretKey::from_slice(&bytes).map_err(
+            |err| decoder.error(&format!("{}", err)))
+    }
+}
+
 pub fn gen_keypair() -> (PublicKey, SecretKey) {
     let (pk, sk) = ed25519::gen_keypair();
     (PublicKey(pk.0), SecretKey(sk.0))
 }
 
+pub fn gen_keypair_from_seed(seed: &[u8]) -> (PublicKey, SecretKey) {
+    let mut seed_bytes = [0; SEEDBYTES];
+    copy_memory(&mut seed_bytes, &hash(seed).0[..SEEDBYTES]);
+    let (pk, sk) = ed25519::keypair_from_seed(&ed25519::Seed(seed_bytes));
+    (PublicKey(pk.0), SecretKey(sk.0))
+}
+
+// Child keys are seeded from the master secret key and the index, so the
+// same master always derives the same keys. Knowing a child key reveals
+// nothing about the master or its other children.
+pub fn derive_keypair(master: &SecretKey, index: u32)
+                      -> (PublicKey, SecretKey) {
+    let mut seed = b"derive_keypair".to_vec();
+    seed.push_all(master.as_slice());
+    for shift in [24, 16, 8, 0].iter() {
+        seed.push((index >> *shift) as u8);
+    }
+    let keypair = gen_keypair_from_seed(&seed);
+    for byte in seed.iter_mut() { *byte = 0; }
+    keypair
+}
+
 // Signature:
 
 pub struct Signature(pub [u8; SIGNATUREBYTES]);
@@ -206,7 +347,8 @@ impl Signature {
 
     pub fn from_slice(bytes: &[u8]) -> IroncResult<Signature> {
         if bytes.len() != SIGNATUREBYTES {
-            Err(IroncError::new(
+            Err(IroncError::with_kind(
+                ErrorKind::Decode,
                 &format!("Invalid signature length {} != {} (required)",
                          bytes.len(), SIGNATUREBYTES)))
         } else {
@@ -245,6 +387,9 @@ impl fmt::Display for Signature {
     }
 }
 
+// Signatures are detached ed25519 signatures (via sodiumoxide). ed25519 does
+// not allow recovering the public key from a signature, which is why signed
+// messages carry the signer's public key alongside (e.g. Block.staker_pk).
 pub fn sign(secret_key: &SecretKey, message: &[u8]) -> Signature {
     Signature(ed25519::sign_detached(message, &ed25519::SecretKey(secret_key.0)).0)
 }
@@ -254,32 +399,168 @@ pub fn verify_signature(public_key: &PublicKey, message: &[u8],
     if ed25519::verify_detached(&ed25519::Signature(signature.0),
                                 message, &ed25519::PublicKey(public_key.0)) {
         Ok(())
-    } else { Err(IroncError::new("Invalid signature.")) }
+    } else {
+        Err(IroncError::with_kind(ErrorKind::SignatureInvalid,
+                                  "Invalid signature."))
+    }
 }
 
 // Utilities for crypto on protobufs:
 
+// The hash function used for hashing messages. Sha512 is what is used by
+// default and for everything in the chain.
+pub trait HashFunction {
+    fn new() -> Self;
+    fn update(&mut self, bytes: &[u8]);
+    fn finalize(self) -> HashDigest;
+}
+
+pub struct Sha512(sha512::State);
+
+impl HashFunction for Sha512 {
+    fn new() -> Sha512 { Sha512(sha512::State::new()) }
+
+    fn update(&mut self, bytes: &[u8]) { self.0.update(bytes); }
+
+    fn finalize(self) -> HashDigest { HashDigest(self.0.finalize().0) }
+}
+
+// Hashes bytes as they are written, so messages can be hashed without first
+// serializing them to a buffer.
+struct HashWriter<H: HashFunction> {
+    hasher: H
+}
+
+impl<H: HashFunction> Write for HashWriter<H> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.hasher.update(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
 pub fn hash_message<M: MessageStatic>(message: &M) -> HashDigest {
-    hash(&message.write_to_bytes().unwrap())
+    hash_message_with::<Sha512, M>(message)
+}
+
+pub fn hash_message_with<H: HashFunction, M: MessageStatic>(message: &M)
+                                                            -> HashDigest {
+    let mut writer = HashWriter { hasher: H::new() };
+    message.write_to_writer(&mut writer).unwrap();
+    writer.hasher.finalize()
+}
+
+// The bytes which are signed for a message, for signing it elsewhere (e.g.
+// on a hardware wallet) without handing over the secret key.
+pub fn signing_payload<M: MessageStatic>(message: &M) -> Vec<u8> {
+    message.write_to_bytes().unwrap()
 }
 
 pub fn sign_message<M: MessageStatic>(
     secret_key: &SecretKey, message: &M) -> Signature {
-    let msg_bytes = &message.write_to_bytes().unwrap();
-    sign(secret_key, msg_bytes)
+    sign(secret_key, &signing_payload(message))
 }
 
 pub fn verify_signed_message<M: MessageStatic>(
     public_key: &PublicKey, message: &M, signature: &Signature)
     -> IroncResult<()> {
-    let msg_bytes: &[u8] = &message.write_to_bytes().unwrap();
-    verify_signature(public_key, msg_bytes, signature)
+    verify_signature(public_key, &signing_payload(message), signature)
+}
+
+pub fn verify_message<M: MessageStatic>(
+    public_key: &PublicKey, signature: &Signature, message: &M) -> bool {
+    verify_signed_message(public_key, message, signature).is_ok()
+}
+
+// The signature schemes keys and signatures may come from, recorded in
+// Block.signature_scheme. ed25519 is the only one sodiumoxide provides, and
+// what the functions above use.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SignatureScheme {
+    Ed25519
+}
+
+pub const DEFAULT_SIGNATURE_SCHEME: SignatureScheme = SignatureScheme::Ed25519;
+
+impl SignatureScheme {
+    pub fn gen_keypair(&self) -> (PublicKey, SecretKey) {
+        match *self {
+            SignatureScheme::Ed25519 => gen_keypair()
+        }
+    }
+
+    pub fn sign_message<M: MessageStatic>(
+        &self, secret_key: &SecretKey, message: &M) -> Signature {
+        match *self {
+            SignatureScheme::Ed25519 => sign_message(secret_key, message)
+        }
+    }
+
+    pub fn verify_signed_message<M: MessageStatic>(
+        &self, public_key: &PublicKey, message: &M, signature: &Signature)
+        -> IroncResult<()> {
+        match *self {
+            SignatureScheme::Ed25519 =>
+                verify_signed_message(public_key, message, signature)
+        }
+    }
+
+    pub fn verify_message<M: MessageStatic>(
+        &self, public_key: &PublicKey, signature: &Signature, message: &M)
+        -> bool {
+        self.verify_signed_message(public_key, message, signature).is_ok()
+    }
 }
 
 /*****  Tests  *****/
 
 use rustc_serialize::json;
 
+#[test]
+fn test_secret_key_public_key() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    assert_eq!(pk1, sk1.public_key());
+    assert!(pk2 != sk1.public_key());
+}
+
+#[test]
+fn test_gen_keypair_from_seed() {
+    let (pk1, sk1) = gen_keypair_from_seed(b"seed");
+    let (pk2, sk2) = gen_keypair_from_seed(b"seed");
+    let (pk3, _) = gen_keypair_from_seed(b"another seed");
+    assert_eq!(pk1, pk2);
+    assert_eq!(sk1, sk2);
+    assert!(pk1 != pk3);
+    assert_eq!(pk1, sk1.public_key());
+    let signature = sign(&sk1, b"message");
+    assert!(verify_signature(&pk1, b"message", &signature).is_ok());
+}
+
+#[test]
+fn test_verify_message() {
+    use tx::TransactionBuilder;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let transaction = tx_builder.build().unwrap();
+
+    let signature = sign_message(&sk1, &transaction);
+    assert!(verify_message(&pk1, &signature, &transaction));
+    assert!(!verify_message(&pk2, &signature, &transaction));
+    assert!(!verify_message(&pk2, &sign_message(&sk1, &transaction),
+                            &transaction));
+    assert!(verify_message(&pk2, &sign_message(&sk2, &transaction),
+                           &transaction));
+
+    let mut flipped = signature.clone();
+    flipped.0[0] ^= 0x01;
+    assert!(!verify_message(&pk1, &flipped, &transaction));
+}
+
 #[test]
 fn test_digest_from_u64() {
     let mut hash1 = HashDigest::from_u64(2100);
@@ -295,6 +576,19 @@ fn test_digest_from_u64() {
     }
 }
 
+#[test]
+fn test_digest_zero_and_empty() {
+    assert!(HashDigest::zero().is_zero());
+    assert_eq!(HashDigest::from_u64(0), HashDigest::zero());
+    assert!(!HashDigest::from_u64(1).is_zero());
+    let mut high_bit = HashDigest::zero();
+    high_bit.0[HASHBYTES - 1] = 0x80;
+    assert!(!high_bit.is_zero());
+
+    assert_eq!(hash(b""), HashDigest::of_empty());
+    assert!(!HashDigest::of_empty().is_zero());
+}
+
 #[test]
 fn test_digest_multiply_u8() {
     let mut hash1 = HashDigest::from_u64(101);
@@ -343,6 +637,56 @@ fn test_digest_add() {
 //     // hash3.multiply
 // }
 
+#[test]
+fn test_digest_hex_round_trip() {
+    use std::ascii::AsciiExt;
+
+    let digest = hash(b"hello world");
+    let digest_hex = digest.to_hex();
+    assert_eq!(2 * HASHBYTES, digest_hex.len());
+    assert_eq!(digest, HashDigest::from_hex(&digest_hex).unwrap());
+    assert_eq!(digest,
+               HashDigest::from_hex(&digest_hex.to_ascii_uppercase()).unwrap());
+}
+
+#[test]
+fn test_digest_display_hex() {
+    let digest = HashDigest::from_u64(0xab01);
+    let mut expected = String::from_str("01ab");
+    for _ in range(2, HASHBYTES) { expected.push_str("00"); }
+    assert_eq!(expected, format!("{}", digest));
+    assert_eq!(digest.to_hex(), format!("{}", digest));
+    assert_eq!(digest.as_slice().to_base64(base64::STANDARD), digest.to_base64());
+}
+
+#[test]
+fn test_digest_from_hex_invalid() {
+    let digest_hex = hash(b"hello world").to_hex();
+    assert!(HashDigest::from_hex("").is_err());
+    assert!(HashDigest::from_hex("abc").is_err());
+    assert!(HashDigest::from_hex(&digest_hex[1..]).is_err());
+
+    let mut with_g = String::from_str("g");
+    with_g.push_str(&digest_hex[1..]);
+    assert_eq!(2 * HASHBYTES, with_g.len());
+    assert!(HashDigest::from_hex(&with_g).is_err());
+}
+
+#[test]
+fn test_digest_from_slice_lengths() {
+    let digest = hash(b"hello world");
+    assert_eq!(digest, HashDigest::from_slice(digest.as_slice()).unwrap());
+
+    let mut long_bytes = digest.as_slice().to_vec();
+    long_bytes.push(0);
+    for bytes in [&[][..], &digest.0[..1], &digest.0[..HASHBYTES - 1],
+                  &long_bytes[..]].iter() {
+        let err = HashDigest::from_slice(bytes).unwrap_err();
+        assert_eq!(ErrorKind::Decode, err.kind());
+        assert!(format!("{}", err).contains(&format!("{}", bytes.len())));
+    }
+}
+
 #[test]
 fn test_digest_ord() {
     let hashes = [
@@ -358,25 +702,48 @@ fn test_digest_ord() {
     }
 }
 
-// #[test]
-// fn test_digest_encoding() {
-//     let hash1 = hash(b"hello world2");
-//     let hash2 = HashDigest::from_u64(123456);
+#[test]
+fn test_digest_encoding() {
+    let hash1 = hash(b"hello world2");
+    let hash2 = HashDigest::from_u64(123456);
 
-//     let hash1_enc = json::encode(&hash1).unwrap();
-//     let hash2_enc = json::encode(&hash2).unwrap();
+    let hash1_enc = json::encode(&hash1).unwrap();
+    let hash2_enc = json::encode(&hash2).unwrap();
+    assert_eq!(format!("\"{}\"", hash1.to_hex()), hash1_enc);
 
-//     let hash1_dec: HashDigest = json::decode(&hash1_enc).unwrap();
-//     let hash2_dec: HashDigest = json::decode(&hash2_enc).unwrap();
+    let hash1_dec: HashDigest = json::decode(&hash1_enc).unwrap();
+    let hash2_dec: HashDigest = json::decode(&hash2_enc).unwrap();
+    assert!(hash1 == hash1_dec);
+    assert!(hash2 == hash2_dec);
 
-//     println!("hash1 = {}", hash1_enc);
-//     println!("hash2 = {}", hash2_enc);
-//     println!("hash1_dec = {}", hash1_dec);
-//     println!("hash2_dec = {}", hash2_dec);
+    let short_enc = json::encode(&hash1.to_hex()[2..].to_string()).unwrap();
+    assert!(json::decode::<HashDigest>(&short_enc).is_err());
+    assert!(json::decode::<HashDigest>("\"not hex\"").is_err());
+    assert!(json::decode::<HashDigest>("12").is_err());
+}
 
-//     assert!(hash1 == hash1_dec);
-//     assert!(hash2 == hash2_dec);
-// }
+#[test]
+fn test_public_key_encoding() {
+    let (pk, _) = gen_keypair();
+    let pk_enc = json::encode(&pk).unwrap();
+    assert_eq!(format!("\"{}\"", pk.as_slice().to_hex()), pk_enc);
+    let pk_dec: PublicKey = json::decode(&pk_enc).unwrap();
+    assert_eq!(pk, pk_dec);
+
+    let long_enc = json::encode(&hash(b"hello").to_hex()).unwrap();
+    assert!(json::decode::<PublicKey>(&long_enc).is_err());
+    assert!(json::decode::<PublicKey>("\"zz\"").is_err());
+}
+
+#[cfg(feature = "serialize-secret-key")]
+#[test]
+fn test_secret_key_encoding() {
+    let (pk, sk) = gen_keypair();
+    let sk_dec: SecretKey = json::decode(&json::encode(&sk).unwrap()).unwrap();
+    assert_eq!(sk, sk_dec);
+    assert_eq!(pk, sk_dec.public_key());
+    assert!(json::decode::<SecretKey>(&json::encode(&pk).unwrap()).is_err());
+}
 
 // #[test]
 // fn test_digest() {
@@ -394,3 +761,221 @@ fn test_digest_ord() {
 //         }
 //     }
 // }
+
+#[test]
+fn test_digest_leading_zero_bits() {
+    assert_eq!(HASHBYTES as u32 * 8, HashDigest::from_u64(0).leading_zero_bits());
+    assert_eq!(HASHBYTES as u32 * 8 - 1,
+               HashDigest::from_u64(1).leading_zero_bits());
+    let mut digest = HashDigest::from_u64(0);
+    digest.0[HASHBYTES - 1] = 0x80;
+    assert_eq!(0, digest.leading_zero_bits());
+    digest.0[HASHBYTES - 1] = 0x01;
+    assert_eq!(7, digest.leading_zero_bits());
+    digest.0[HASHBYTES - 1] = 0;
+    digest.0[HASHBYTES - 3] = 0x10;
+    assert_eq!(19, digest.leading_zero_bits());
+}
+
+#[test]
+fn test_key_and_signature_lengths() {
+    assert_eq!(32, PUBLICKEYBYTES);
+    assert_eq!(64, SECRETKEYBYTES);
+    assert_eq!(64, SIGNATUREBYTES);
+    assert_eq!(64, HASHBYTES);
+
+    let (pk, sk) = gen_keypair();
+    let signature = sign(&sk, b"message");
+    assert_eq!(PUBLICKEYBYTES, pk.as_slice().len());
+    assert_eq!(SECRETKEYBYTES, sk.as_slice().len());
+    assert_eq!(SIGNATUREBYTES, signature.as_slice().len());
+
+    assert!(PublicKey::from_slice(&[0; 31]).is_err());
+    assert!(PublicKey::from_slice(&[0; 33]).is_err());
+    assert!(SecretKey::from_slice(&[0; 63]).is_err());
+    assert!(Signature::from_slice(&[0; 63]).is_err());
+    assert!(Signature::from_slice(&[0; 65]).is_err());
+    assert_eq!(signature, Signature::from_slice(signature.as_slice()).unwrap());
+}
+
+#[test]
+fn test_sign_ed25519_vector() {
+    // Test vector 1 from RFC 8032, section 7.1 (empty message).
+    let seed = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60"
+        .from_hex().unwrap();
+    let expected_pk =
+        "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        .from_hex().unwrap();
+    let expected_signature =
+        "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+         5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        .from_hex().unwrap();
+
+    let mut seed_bytes = [0; SEEDBYTES];
+    copy_memory(&mut seed_bytes, &seed);
+    let (pk, sk) = ed25519::keypair_from_seed(&ed25519::Seed(seed_bytes));
+    let public_key = PublicKey(pk.0);
+    let secret_key = SecretKey(sk.0);
+    assert_eq!(expected_pk, public_key.as_slice());
+
+    let signature = sign(&secret_key, b"");
+    assert_eq!(expected_signature, signature.as_slice());
+    assert!(verify_signature(&public_key, b"", &signature).is_ok());
+    assert!(verify_signature(&public_key, b"x", &signature).is_err());
+}
+
+#[test]
+fn test_keypair_bytes_round_trip() {
+    let (pk, sk) = gen_keypair();
+    let pk_bytes = pk.as_slice().to_vec();
+    let sk_bytes = sk.as_slice().to_vec();
+
+    let loaded_pk = PublicKey::from_slice(&pk_bytes).unwrap();
+    let loaded_sk = SecretKey::from_slice(&sk_bytes).unwrap();
+    assert_eq!(pk, loaded_pk);
+    assert_eq!(sk, loaded_sk);
+
+    let signature = sign(&loaded_sk, b"persisted");
+    assert!(verify_signature(&pk, b"persisted", &signature).is_ok());
+    assert!(verify_signature(&loaded_pk, b"persisted",
+                             &sign(&sk, b"persisted")).is_ok());
+
+    assert!(PublicKey::from_slice(&sk_bytes).is_err());
+    assert!(SecretKey::from_slice(&pk_bytes).is_err());
+    assert!(SecretKey::from_slice(&[]).is_err());
+}
+
+#[test]
+fn test_digest_ct_eq() {
+    let digest1 = hash(b"hello world");
+    let digest2 = hash(b"hello world");
+    assert!(digest1.ct_eq(&digest2));
+    assert!(digest1.ct_eq(&digest1));
+    assert!(!digest1.ct_eq(&hash(b"hello world!")));
+    for index in range(0, HASHBYTES) {
+        let mut flipped = HashDigest::from_slice(&digest1.0).unwrap();
+        flipped.0[index] ^= 0x40;
+        assert!(!digest1.ct_eq(&flipped));
+        assert!(!flipped.ct_eq(&digest1));
+        assert!(digest1 != flipped);
+    }
+}
+
+#[test]
+fn test_hash_message_streaming() {
+    use protobuf::Message;
+    use ironcoin_pb::{SignedBlock, Transaction, Transfer};
+
+    let (pk, sk) = gen_keypair();
+    let mut signed_block = SignedBlock::new();
+    for index in range(0, 2000u32) {
+        let mut transfer = Transfer::new();
+        transfer.set_op_index(index);
+        transfer.set_tokens(index as u64 * 1000);
+        transfer.set_source_pk(pk.0.to_vec());
+        transfer.set_destination_pk(pk.0.to_vec());
+        let mut transaction = Transaction::new();
+        transaction.mut_commit().mut_transfers().push(transfer);
+        signed_block.mut_block().mut_transactions().push(transaction);
+    }
+    let signature = sign_message(&sk, signed_block.get_block());
+    signed_block.set_signature(signature.0.to_vec());
+
+    let one_shot = hash(&signed_block.write_to_bytes().unwrap());
+    assert_eq!(one_shot, hash_message(&signed_block));
+    assert_eq!(hash(&[]), hash_message(&SignedBlock::new()));
+}
+
+// Digest of the number of bytes hashed, to check which hash function is used.
+#[cfg(test)]
+pub struct ByteCountHash(u64);
+
+#[cfg(test)]
+impl HashFunction for ByteCountHash {
+    fn new() -> ByteCountHash { ByteCountHash(0) }
+
+    fn update(&mut self, bytes: &[u8]) { self.0 += bytes.len() as u64; }
+
+    fn finalize(self) -> HashDigest { HashDigest::from_u64(self.0) }
+}
+
+#[test]
+fn test_hash_message_with() {
+    use protobuf::Message;
+    use ironcoin_pb::Transfer;
+
+    let mut transfer = Transfer::new();
+    transfer.set_tokens(1000);
+    transfer.set_source_pk(vec![1; PUBLICKEYBYTES]);
+    let transfer_bytes = transfer.write_to_bytes().unwrap();
+    assert_eq!(HashDigest::from_u64(transfer_bytes.len() as u64),
+               hash_message_with::<ByteCountHash, _>(&transfer));
+    assert_eq!(hash(&transfer_bytes), hash_message_with::<Sha512, _>(&transfer));
+    assert_eq!(hash(&transfer_bytes), hash_message(&transfer));
+}
+
+#[test]
+fn test_signing_payload() {
+    use protobuf::Message;
+    use block::{BlockBuilder, BlockExt, HashedBlockExt, SignedBlockExt};
+
+    let (pk1, sk1) = gen_keypair();
+    let mut block = BlockBuilder::new(HashDigest::from_u64(1), 1)
+        .build(&sk1).unwrap();
+    let header = block.get_signed_block().get_block().header();
+    assert_eq!(header.write_to_bytes().unwrap(), signing_payload(&header));
+
+    // Signed without going through sign_message, as an external signer would.
+    let signature = sign(&sk1, &signing_payload(&header));
+    assert_eq!(signature, sign_message(&sk1, &header));
+    assert!(verify_signed_message(&pk1, &header, &signature).is_ok());
+    block.mut_signed_block().set_signature(signature.0.to_vec());
+    block.compute_hash();
+    assert!(block.get_signed_block().verify_signature().is_ok());
+    assert!(block.verify().is_ok());
+}
+
+#[test]
+fn test_signature_scheme_ed25519() {
+    use tx::TransactionBuilder;
+
+    let scheme = DEFAULT_SIGNATURE_SCHEME;
+    assert_eq!(SignatureScheme::Ed25519, scheme);
+    let (pk1, sk1) = scheme.gen_keypair();
+    let (pk2, _) = scheme.gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let transaction = tx_builder.build().unwrap();
+
+    // ed25519 signatures are deterministic, so both must agree exactly.
+    let signature = scheme.sign_message(&sk1, &transaction);
+    assert_eq!(sign_message(&sk1, &transaction), signature);
+    assert!(scheme.verify_message(&pk1, &signature, &transaction));
+    assert!(!scheme.verify_message(&pk2, &signature, &transaction));
+    assert_eq!(ErrorKind::SignatureInvalid,
+               scheme.verify_signed_message(&pk2, &transaction, &signature)
+               .unwrap_err().kind());
+}
+
+#[test]
+fn test_derive_keypair() {
+    let (_, master) = gen_keypair();
+    let (pk0, sk0) = derive_keypair(&master, 0);
+    let (pk1, sk1) = derive_keypair(&master, 1);
+    assert!(pk0 != pk1);
+    assert_eq!(pk0, sk0.public_key());
+    assert_eq!(pk1, sk1.public_key());
+    assert!(pk0 != master.public_key());
+    assert_eq!((pk0.clone(), sk0.clone()), derive_keypair(&master, 0));
+    assert_eq!(pk1, derive_keypair(&master, 1).0);
+
+    let (_, other_master) = gen_keypair();
+    assert!(pk0 != derive_keypair(&other_master, 0).0);
+    assert!(derive_keypair(&master, 256).0 != derive_keypair(&master, 1).0);
+
+    let signature0 = sign(&sk0, b"message");
+    let signature1 = sign(&sk1, b"message");
+    assert!(verify_signature(&pk0, b"message", &signature0).is_ok());
+    assert!(verify_signature(&pk1, b"message", &signature1).is_ok());
+    assert!(verify_signature(&pk1, b"message", &signature0).is_err());
+}
@@ -7,15 +7,32 @@ use rustc_serialize;
 
 pub type IroncResult<Msg> = Result<Msg, IroncError>;
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    Other,
+    Conservation,
+    Decode,
+    DoubleSpend,
+    HashMismatch,
+    SignatureInvalid
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct IroncError {
+    kind: ErrorKind,
     description: String
 }
 
 impl IroncError {
     pub fn new(description: &str) -> IroncError {
-        IroncError { description: String::from_str(description) }
+        IroncError::with_kind(ErrorKind::Other, description)
     }
+
+    pub fn with_kind(kind: ErrorKind, description: &str) -> IroncError {
+        IroncError { kind: kind, description: String::from_str(description) }
+    }
+
+    pub fn kind(&self) -> ErrorKind { self.kind }
 }
 
 impl Display for IroncError {
@@ -41,26 +58,66 @@ impl Error for IroncError {
 //     }
 // }
 
-trait ConvertToIroncError: Error {}
-impl ConvertToIroncError for protobuf::error::ProtobufError {}
+trait ConvertToIroncError: Error {
+    fn error_kind(&self) -> ErrorKind { ErrorKind::Other }
+}
+impl ConvertToIroncError for protobuf::error::ProtobufError {
+    fn error_kind(&self) -> ErrorKind { ErrorKind::Decode }
+}
 impl ConvertToIroncError for ::std::io::Error {}
 impl ConvertToIroncError for rustc_serialize::json::EncoderError {}
-impl ConvertToIroncError for rustc_serialize::base64::FromBase64Error {}
+impl ConvertToIroncError for rustc_serialize::base64::FromBase64Error {
+    fn error_kind(&self) -> ErrorKind { ErrorKind::Decode }
+}
 
 impl<Err: ConvertToIroncError> FromError<Err> for IroncError {
     fn from_error(err: Err) -> IroncError {
-        IroncError { description: String::from_str(err.description()) }
+        IroncError::with_kind(err.error_kind(), err.description())
     }
 }
 
 impl FromError<nanomsg::NanoError> for IroncError {
     fn from_error(err: nanomsg::NanoError) -> IroncError {
-        IroncError { description: String::from_str(err.description) }
+        IroncError::new(&err.description)
     }
 }
 
 impl<'a> FromError<&'a str> for IroncError {
     fn from_error(err: &'a str) -> IroncError {
-        IroncError { description: String::from_str(err) }
+        IroncError::new(err)
     }
 }
+
+/*****  Tests  *****/
+
+#[test]
+fn test_error_kind() {
+    assert_eq!(ErrorKind::Other, IroncError::new("some error").kind());
+    let err = IroncError::with_kind(ErrorKind::Decode, "bad bytes");
+    assert_eq!(ErrorKind::Decode, err.kind());
+    assert_eq!("bad bytes", err.description());
+}
+
+#[test]
+fn test_error_boxed_display() {
+    let err: Box<Error> = Box::new(IroncError::with_kind(
+        ErrorKind::HashMismatch, "Block has invalid hash"));
+    assert_eq!("Block has invalid hash", format!("{}", err));
+    assert_eq!("Block has invalid hash", err.description());
+    assert!(err.cause().is_none());
+
+    let result: Result<(), Box<Error>> =
+        Err(Box::new(IroncError::new("Invalid signature.")));
+    assert_eq!("Invalid signature.", format!("{}", result.unwrap_err()));
+}
+
+#[test]
+fn test_error_from_base64_error() {
+    use rustc_serialize::base64::FromBase64;
+
+    let base64_err = "not base64!".from_base64().unwrap_err();
+    let description = String::from_str(base64_err.description());
+    let err: IroncError = FromError::from_error(base64_err);
+    assert_eq!(ErrorKind::Decode, err.kind());
+    assert_eq!(description, err.description());
+}
@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+
+use protobuf::ProtobufEnum;
+use rustc_serialize::hex::{FromHex, ToHex};
+use rustc_serialize::json::{Json, Object};
+
+use error::{ErrorKind, IroncError, IroncResult};
+use ironcoin_pb::{Block, Block_SignatureScheme, Commitment, Commitment_Type,
+                  DetachedSignature, HashedBlock, SignedBlock, Transaction,
+                  Transfer};
+
+// JSON encoding of blocks for external consumers. Byte fields are hex
+// encoded and 64 bit amounts are written as base 10 strings, as they do not
+// fit in a double. Only fields which are set in the protobuf are emitted, so
+// that decoding gives back a block with the same hash.
+
+pub fn encode_block(block: &HashedBlock) -> Json {
+    let mut object = BTreeMap::new();
+    if block.has_hash() { insert_hex(&mut object, "hash", block.get_hash()); }
+    if block.has_signed_block() {
+        object.insert(String::from_str("signed_block"),
+                      encode_signed_block(block.get_signed_block()));
+    }
+    Json::Object(object)
+}
+
+pub fn decode_block(json: &Json) -> IroncResult<HashedBlock> {
+    let object = try!(as_object(json, "hashed block"));
+    let mut block = HashedBlock::new();
+    if let Some(hash) = try!(get_hex(object, "hash")) { block.set_hash(hash); }
+    if let Some(signed_block) = object.get("signed_block") {
+        block.set_signed_block(try!(decode_signed_block(signed_block)));
+    }
+    Ok(block)
+}
+
+fn encode_signed_block(signed_block: &SignedBlock) -> Json {
+    let mut object = BTreeMap::new();
+    if signed_block.has_signature() {
+        insert_hex(&mut object, "signature", signed_block.get_signature());
+    }
+    if signed_block.has_block() {
+        object.insert(String::from_str("block"),
+                      encode_header_and_transactions(signed_block.get_block()));
+    }
+    if signed_block.has_difficulty() {
+        object.insert(String::from_str("difficulty"),
+                      Json::U64(signed_block.get_difficulty() as u64));
+    }
+    if signed_block.has_nonce() {
+        insert_u64_str(&mut object, "nonce", signed_block.get_nonce());
+    }
+    Json::Object(object)
+}
+
+fn decode_signed_block(json: &Json) -> IroncResult<SignedBlock> {
+    let object = try!(as_object(json, "signed block"));
+    let mut signed_block = SignedBlock::new();
+    if let Some(signature) = try!(get_hex(object, "signature")) {
+        signed_block.set_signature(signature);
+    }
+    if let Some(block) = object.get("block") {
+        signed_block.set_block(try!(decode_header_and_transactions(block)));
+    }
+    if let Some(difficulty) = try!(get_u32(object, "difficulty")) {
+        signed_block.set_difficulty(difficulty);
+    }
+    if let Some(nonce) = try!(get_u64_str(object, "nonce")) {
+        signed_block.set_nonce(nonce);
+    }
+    Ok(signed_block)
+}
+
+fn encode_header_and_transactions(block: &Block) -> Json {
+    let mut object = BTreeMap::new();
+    if block.has_staker_pk() {
+        insert_hex(&mut object, "staker_pk", block.get_staker_pk());
+    }
+    if block.has_previous() {
+        insert_hex(&mut object, "previous", block.get_previous());
+    }
+    if block.has_timestamp() {
+        object.insert(String::from_str("timestamp"),
+                      Json::I64(block.get_timestamp()));
+    }
+    if block.has_height() {
+        object.insert(String::from_str("height"),
+                      Json::U64(block.get_height() as u64));
+    }
+    if block.has_target_hash() {
+        insert_hex(&mut object, "target_hash", block.get_target_hash());
+    }
+    if block.has_merkle_root() {
+        insert_hex(&mut object, "merkle_root", block.get_merkle_root());
+    }
+    if block.has_signature_scheme() {
+        object.insert(String::from_str("signature_scheme"),
+                      Json::I64(block.get_signature_scheme().value() as i64));
+    }
+    object.insert(String::from_str("transactions"), Json::Array(
+        block.get_transactions().iter().map(encode_transaction).collect()));
+    Json::Object(object)
+}
+
+fn decode_header_and_transactions(json: &Json) -> IroncResult<Block> {
+    let object = try!(as_object(json, "block"));
+    let mut block = Block::new();
+    if let Some(staker_pk) = try!(get_hex(object, "staker_pk")) {
+        block.set_staker_pk(staker_pk);
+    }
+    if let Some(previous) = try!(get_hex(object, "previous")) {
+        block.set_previous(previous);
+    }
+    if let Some(timestamp) = object.get("timestamp") {
+        block.set_timestamp(try!(timestamp.as_i64().ok_or(
+            decode_error("\"timestamp\" must be an integer"))));
+    }
+    if let Some(height) = try!(get_u32(object, "height")) {
+        block.set_height(height);
+    }
+    if let Some(target_hash) = try!(get_hex(object, "target_hash")) {
+        block.set_target_hash(target_hash);
+    }
+    if let Some(merkle_root) = try!(get_hex(object, "merkle_root")) {
+        block.set_merkle_root(merkle_root);
+    }
+    if let Some(scheme) = object.get("signature_scheme") {
+        let scheme = try!(
+            scheme.as_i64()
+                .and_then(|value| Block_SignatureScheme::from_i32(value as i32))
+                .ok_or(decode_error("invalid \"signature_scheme\"")));
+        block.set_signature_scheme(scheme);
+    }
+    for tx in try!(get_array(object, "transactions")).iter() {
+        block.mut_transactions().push(try!(decode_transaction(tx)));
+    }
+    Ok(block)
+}
+
+pub fn encode_transaction(transaction: &Transaction) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert(String::from_str("signatures"), Json::Array(
+        transaction.get_signatures().iter().map(|signature| {
+            let mut signature_object = BTreeMap::new();
+            if signature.has_public_key() {
+                insert_hex(&mut signature_object, "public_key",
+                           signature.get_public_key());
+            }
+            if signature.has_payload() {
+                insert_hex(&mut signature_object, "payload",
+                           signature.get_payload());
+            }
+            Json::Object(signature_object)
+        }).collect()));
+    if transaction.has_commit() {
+        object.insert(String::from_str("commit"),
+                      encode_commitment(transaction.get_commit()));
+    }
+    Json::Object(object)
+}
+
+pub fn decode_transaction(json: &Json) -> IroncResult<Transaction> {
+    let object = try!(as_object(json, "transaction"));
+    let mut transaction = Transaction::new();
+    for signature_json in try!(get_array(object, "signatures")).iter() {
+        let signature_object = try!(as_object(signature_json, "signature"));
+        let mut signature = DetachedSignature::new();
+        if let Some(public_key) = try!(get_hex(signature_object, "public_key")) {
+            signature.set_public_key(public_key);
+        }
+        if let Some(payload) = try!(get_hex(signature_object, "payload")) {
+            signature.set_payload(payload);
+        }
+        transaction.mut_signatures().push(signature);
+    }
+    if let Some(commit) = object.get("commit") {
+        transaction.set_commit(try!(decode_commitment(commit)));
+    }
+    Ok(transaction)
+}
+
+fn encode_commitment(commit: &Commitment) -> Json {
+    let mut object = BTreeMap::new();
+    if commit.has_tx_type() {
+        object.insert(String::from_str("tx_type"),
+                      Json::I64(commit.get_tx_type().value() as i64));
+    }
+    if commit.has_bounty_pk() {
+        insert_hex(&mut object, "bounty_pk", commit.get_bounty_pk());
+    }
+    if commit.has_bounty() {
+        insert_u64_str(&mut object, "bounty", commit.get_bounty());
+    }
+    object.insert(String::from_str("transfers"), Json::Array(
+        commit.get_transfers().iter().map(encode_transfer).collect()));
+    Json::Object(object)
+}
+
+fn decode_commitment(json: &Json) -> IroncResult<Commitment> {
+    let object = try!(as_object(json, "commitment"));
+    let mut commit = Commitment::new();
+    if let Some(tx_type) = object.get("tx_type") {
+        let tx_type = try!(
+            tx_type.as_i64()
+                .and_then(|value| Commitment_Type::from_i32(value as i32))
+                .ok_or(decode_error("invalid \"tx_type\"")));
+        commit.set_tx_type(tx_type);
+    }
+    if let Some(bounty_pk) = try!(get_hex(object, "bounty_pk")) {
+        commit.set_bounty_pk(bounty_pk);
+    }
+    if let Some(bounty) = try!(get_u64_str(object, "bounty")) {
+        commit.set_bounty(bounty);
+    }
+    for transfer in try!(get_array(object, "transfers")).iter() {
+        commit.mut_transfers().push(try!(decode_transfer(transfer)));
+    }
+    Ok(commit)
+}
+
+fn encode_transfer(transfer: &Transfer) -> Json {
+    let mut object = BTreeMap::new();
+    if transfer.has_op_index() {
+        object.insert(String::from_str("op_index"),
+                      Json::U64(transfer.get_op_index() as u64));
+    }
+    if transfer.has_tokens() {
+        insert_u64_str(&mut object, "tokens", transfer.get_tokens());
+    }
+    if transfer.has_source_pk() {
+        insert_hex(&mut object, "source_pk", transfer.get_source_pk());
+    }
+    if transfer.has_destination_pk() {
+        insert_hex(&mut object, "destination_pk", transfer.get_destination_pk());
+    }
+    if transfer.has_fee() {
+        insert_u64_str(&mut object, "fee", transfer.get_fee());
+    }
+    Json::Object(object)
+}
+
+fn decode_transfer(json: &Json) -> IroncResult<Transfer> {
+    let object = try!(as_object(json, "transfer"));
+    let mut transfer = Transfer::new();
+    if let Some(op_index) = try!(get_u32(object, "op_index")) {
+        transfer.set_op_index(op_index);
+    }
+    if let Some(tokens) = try!(get_u64_str(object, "tokens")) {
+        transfer.set_tokens(tokens);
+    }
+    if let Some(source_pk) = try!(get_hex(object, "source_pk")) {
+        transfer.set_source_pk(source_pk);
+    }
+    if let Some(destination_pk) = try!(get_hex(object, "destination_pk")) {
+        transfer.set_destination_pk(destination_pk);
+    }
+    if let Some(fee) = try!(get_u64_str(object, "fee")) {
+        transfer.set_fee(fee);
+    }
+    Ok(transfer)
+}
+
+fn decode_error(description: &str) -> IroncError {
+    IroncError::with_kind(ErrorKind::Decode, &format!(
+        "Could not decode JSON: {}", description))
+}
+
+fn insert_hex(object: &mut Object, key: &str, bytes: &[u8]) {
+    object.insert(String::from_str(key), Json::String(bytes.to_hex()));
+}
+
+fn insert_u64_str(object: &mut Object, key: &str, value: u64) {
+    object.insert(String::from_str(key), Json::String(format!("{}", value)));
+}
+
+fn as_object<'a>(json: &'a Json, what: &str) -> IroncResult<&'a Object> {
+    json.as_object().ok_or(decode_error(&format!("{} must be an object", what)))
+}
+
+fn get_array<'a>(object: &'a Object, key: &str) -> IroncResult<&'a [Json]> {
+    match object.get(key) {
+        Some(&Json::Array(ref array)) => Ok(array),
+        Some(_) => Err(decode_error(&format!("\"{}\" must be an array", key))),
+        None => Ok(&[])
+    }
+}
+
+fn get_hex(object: &Object, key: &str) -> IroncResult<Option<Vec<u8>>> {
+    match object.get(key) {
+        Some(&Json::String(ref hex_str)) => match hex_str.from_hex() {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) => Err(decode_error(&format!(
+                "\"{}\" is not valid hex: {}", key, err)))
+        },
+        Some(_) => Err(decode_error(&format!("\"{}\" must be a string", key))),
+        None => Ok(None)
+    }
+}
+
+fn get_u32(object: &Object, key: &str) -> IroncResult<Option<u32>> {
+    match object.get(key) {
+        Some(value) => match value.as_u64() {
+            Some(number) if number <= ::std::u32::MAX as u64 =>
+                Ok(Some(number as u32)),
+            _ => Err(decode_error(&format!(
+                "\"{}\" must be a 32 bit unsigned integer", key)))
+        },
+        None => Ok(None)
+    }
+}
+
+fn get_u64_str(object: &Object, key: &str) -> IroncResult<Option<u64>> {
+    match object.get(key) {
+        Some(&Json::String(ref number)) => match number.parse::<u64>() {
+            Ok(number) => Ok(Some(number)),
+            Err(_) => Err(decode_error(&format!(
+                "\"{}\" is not a base 10 integer: {}", key, number)))
+        },
+        Some(_) => Err(decode_error(&format!("\"{}\" must be a string", key))),
+        None => Ok(None)
+    }
+}
+
+/*****  Tests  *****/
+
+use block::{BlockBuilder, GenesisBuilder, HashedBlockExt};
+use crypto::{HashDigest, gen_keypair};
+use tx::TransactionBuilder;
+
+#[test]
+fn test_block_json_round_trip() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut genesis_builder = GenesisBuilder::new();
+    genesis_builder.add_transfer(pk1.clone(), 1000);
+    let genesis = genesis_builder.build().unwrap();
+    let genesis_json = genesis.to_json().unwrap();
+    assert!(genesis == HashedBlock::from_json(&genesis_json).unwrap());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 100, 2, 0)
+        .set_bounty(&sk1, &pk1, 3);
+    let mut block_builder =
+        BlockBuilder::new(genesis.decode_hash().unwrap(), 1);
+    block_builder.add_transaction(tx_builder.build().unwrap())
+        .allow_bounty(true);
+    let mut block = block_builder.build(&sk2).unwrap();
+    block.mine(2).unwrap();
+
+    let decoded = HashedBlock::from_json(&block.to_json().unwrap()).unwrap();
+    assert!(block == decoded);
+    assert!(decoded.verify().is_ok());
+    assert_eq!(block.to_json().unwrap(), decoded.to_json().unwrap());
+}
+
+#[test]
+fn test_block_json_round_trip_signature_scheme() {
+    let (_, sk) = gen_keypair();
+    let mut block = BlockBuilder::new(HashDigest::from_u64(7), 1)
+        .build(&sk).unwrap();
+    block.mut_signed_block().mut_block()
+        .set_signature_scheme(Block_SignatureScheme::ED25519);
+    block.reseal(&sk);
+
+    let decoded = HashedBlock::from_json(&block.to_json().unwrap()).unwrap();
+    assert!(decoded.get_block().has_signature_scheme());
+    assert!(block == decoded);
+    assert!(decoded.verify().is_ok());
+
+    let mut json = Json::from_str(&block.to_json().unwrap()).unwrap();
+    json.as_object_mut().unwrap().get_mut("signed_block").unwrap()
+        .as_object_mut().unwrap().get_mut("block").unwrap()
+        .as_object_mut().unwrap()
+        .insert(String::from_str("signature_scheme"), Json::I64(99));
+    assert!(HashedBlock::from_json(&format!("{}", json)).is_err());
+}
+
+#[test]
+fn test_block_json_hex_fields() {
+    let (_, sk) = gen_keypair();
+    let block = BlockBuilder::new(HashDigest::from_u64(7), 1).build(&sk).unwrap();
+    let json = Json::from_str(&block.to_json().unwrap()).unwrap();
+    let block_hash = block.decode_hash().unwrap();
+    assert_eq!(Some(&block_hash.to_hex()[..]),
+               json.find("hash").and_then(|hash| hash.as_string()));
+    assert_eq!(Some(&HashDigest::from_u64(7).to_hex()[..]),
+               json.find_path(&["signed_block", "block", "previous"])
+                   .and_then(|previous| previous.as_string()));
+}
+
+#[test]
+fn test_block_json_rejects_tampering() {
+    let (_, sk) = gen_keypair();
+    let block = BlockBuilder::new(HashDigest::from_u64(7), 1).build(&sk).unwrap();
+    let mut json = Json::from_str(&block.to_json().unwrap()).unwrap();
+    json.as_object_mut().unwrap().insert(
+        String::from_str("hash"),
+        Json::String(HashDigest::from_u64(1).to_hex()));
+    assert!(HashedBlock::from_json(&format!("{}", json)).is_err());
+    assert!(HashedBlock::from_json("{\"hash\": \"not hex\"}").is_err());
+    assert!(HashedBlock::from_json("[1, 2]").is_err());
+    assert!(HashedBlock::from_json("{").is_err());
+}
@@ -17,12 +17,19 @@ extern crate "rustc-serialize" as rustc_serialize;
 
 mod balance;
 mod block;
+mod blockfile;
 mod blocktree;
+mod bloom;
+mod chain;
+mod codec;
 mod crypto;
 mod error;
 mod app;
 mod service;
 mod ironcoin_pb;
+mod json;
+mod mempool;
+mod merkle;
 mod staking;
 mod store;
 mod tx;
@@ -74,7 +81,7 @@ fn create_genesis_block_from_cmdline(tx_strs: &[String])
         let amount: u64 = maybe_amount.unwrap();
         builder.add_transfer(destination, amount);
     }
-    Ok(builder.build())
+    builder.build()
 }
 
 fn create_block_store(block_db: &str, genesis: Option<HashedBlock>) ->
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::mem::replace;
+
+use crypto::HashDigest;
+use error::{IroncError, IroncResult};
+use ironcoin_pb::Transaction;
+use tx::TransactionExt;
+
+pub struct Mempool {
+    transactions: Vec<Transaction>,
+    tx_ids: HashSet<HashDigest>
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool { transactions: vec![], tx_ids: HashSet::new() }
+    }
+
+    pub fn add(&mut self, transaction: Transaction) -> IroncResult<()> {
+        let tx_id = transaction.compute_id();
+        if self.tx_ids.contains(&tx_id) {
+            return Err(IroncError::new(&format!(
+                "Transaction {} is already in the mempool.", tx_id)));
+        }
+        match transaction.verify_signatures() {
+            Ok(_) => {},
+            Err(err) => return Err(IroncError::new(&format!(
+                "Transaction {} rejected from the mempool: {}",
+                tx_id, err)))
+        }
+        self.tx_ids.insert(tx_id);
+        self.transactions.push(transaction);
+        Ok(())
+    }
+
+    // Removes up to `max` transactions, oldest first.
+    pub fn take(&mut self, max: usize) -> Vec<Transaction> {
+        let num_taken = if max < self.transactions.len() { max }
+                        else { self.transactions.len() };
+        let remaining = self.transactions.split_off(num_taken);
+        let taken = replace(&mut self.transactions, remaining);
+        for tx in taken.iter() { self.tx_ids.remove(&tx.compute_id()); }
+        taken
+    }
+
+    pub fn len(&self) -> usize { self.transactions.len() }
+}
+
+/*****  Tests  *****/
+
+use crypto::gen_keypair;
+use tx::TransactionBuilder;
+
+#[test]
+fn test_mempool_add_dedup() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0);
+    let transaction = tx_builder.build().unwrap();
+
+    let mut mempool = Mempool::new();
+    assert_eq!(0, mempool.len());
+    assert!(mempool.add(transaction.clone()).is_ok());
+    assert_eq!(1, mempool.len());
+    assert!(mempool.add(transaction.clone()).is_err());
+    assert_eq!(1, mempool.len());
+
+    mempool.take(1);
+    assert!(mempool.add(transaction).is_ok());
+}
+
+#[test]
+fn test_mempool_add_rejects_bad_signature() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0);
+    let mut transaction = tx_builder.build().unwrap();
+    transaction.mut_commit().mut_transfers()[0].set_tokens(1000);
+
+    let mut mempool = Mempool::new();
+    assert!(mempool.add(transaction).is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0);
+    let mut transaction = tx_builder.build().unwrap();
+    transaction.mut_signatures().clear();
+    assert!(mempool.add(transaction).is_err());
+    assert_eq!(0, mempool.len());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk2, &pk2, &pk1, 100, 0);
+    assert!(mempool.add(tx_builder.build().unwrap()).is_ok());
+    assert_eq!(1, mempool.len());
+}
+
+#[test]
+fn test_mempool_take_ordering() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut mempool = Mempool::new();
+    let transactions: Vec<Transaction> = range(0, 5).map(|op_index| {
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, op_index);
+        tx_builder.build().unwrap()
+    }).collect();
+    for tx in transactions.iter() { mempool.add(tx.clone()).unwrap(); }
+
+    assert!(mempool.take(0).is_empty());
+    assert!(mempool.take(2) == transactions[..2].to_vec());
+    assert_eq!(3, mempool.len());
+    assert!(mempool.take(10) == transactions[2..].to_vec());
+    assert_eq!(0, mempool.len());
+    assert!(mempool.take(1).is_empty());
+}
@@ -0,0 +1,183 @@
+use crypto::{hash, hash_message, HashDigest};
+use error::{IroncError, IroncResult};
+use ironcoin_pb::Transaction;
+
+const NODE_PREFIX: u8 = 1;
+
+fn hash_pair(left: &HashDigest, right: &HashDigest) -> HashDigest {
+    let mut node_bytes = vec![NODE_PREFIX];
+    node_bytes.push_all(&left.0);
+    node_bytes.push_all(&right.0);
+    hash(&node_bytes)
+}
+
+fn next_level(level: &[HashDigest]) -> Vec<HashDigest> {
+    level.chunks(2).map(|pair| {
+        // An odd node out is paired with itself.
+        if pair.len() == 2 { hash_pair(&pair[0], &pair[1]) }
+        else { hash_pair(&pair[0], &pair[0]) }
+    }).collect()
+}
+
+pub fn transaction_leaves(transactions: &[Transaction]) -> Vec<HashDigest> {
+    transactions.iter().map(|tx| hash_message(tx)).collect()
+}
+
+pub fn merkle_root(leaves: &[HashDigest]) -> HashDigest {
+    if leaves.len() == 0 {
+        return hash(b"");
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.pop().unwrap()
+}
+
+// Pairing an odd node out with itself means a list ending in a duplicated
+// pair, at any level, has the same root as the list without it, e.g.
+// [a, b, c] and [a, b, c, c]. Such lists have to be rejected for the root to
+// commit to a single list of leaves.
+pub fn has_duplicate_pair(leaves: &[HashDigest]) -> bool {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        if level.chunks(2).any(|pair| pair.len() == 2 && pair[0] == pair[1]) {
+            return true;
+        }
+        level = next_level(&level);
+    }
+    false
+}
+
+// The proof lists the sibling of the leaf, then the sibling of its parent and
+// so on up to the root. Whether each sibling goes on the left or the right is
+// given by the bits of the leaf index, which is why verification needs it.
+pub fn merkle_proof(leaves: &[HashDigest], index: usize)
+                    -> IroncResult<Vec<HashDigest>> {
+    if index >= leaves.len() {
+        return Err(IroncError::new(&format!(
+            "Merkle proof requested for leaf {} of {}", index, leaves.len())));
+    }
+    let mut proof = vec![];
+    let mut level = leaves.to_vec();
+    let mut level_index = index;
+    while level.len() > 1 {
+        let sibling_index = level_index ^ 1;
+        if sibling_index < level.len() {
+            proof.push(level[sibling_index].clone());
+        } else {
+            proof.push(level[level_index].clone());
+        }
+        level = next_level(&level);
+        level_index /= 2;
+    }
+    Ok(proof)
+}
+
+// An odd last leaf is paired with itself, so its proof also verifies at the
+// index one past it. Checking the index against the number of leaves rules
+// that out.
+pub fn verify_merkle_proof(leaf: &HashDigest, index: usize, num_leaves: usize,
+                           proof: &[HashDigest], root: &HashDigest) -> bool {
+    if index >= num_leaves {
+        return false;
+    }
+    let mut node = leaf.clone();
+    let mut level_index = index;
+    for sibling in proof.iter() {
+        node = if level_index % 2 == 0 { hash_pair(&node, sibling) }
+               else { hash_pair(sibling, &node) };
+        level_index /= 2;
+    }
+    level_index == 0 && node == *root
+}
+
+/*****  Tests  *****/
+
+#[test]
+fn test_merkle_root_empty() {
+    assert!(hash(b"") == merkle_root(&[]));
+}
+
+#[test]
+fn test_merkle_root_single_and_pairs() {
+    let leaf1 = hash(b"leaf1");
+    let leaf2 = hash(b"leaf2");
+    let leaf3 = hash(b"leaf3");
+    assert!(leaf1 == merkle_root(&[leaf1.clone()]));
+    assert!(hash_pair(&leaf1, &leaf2) ==
+            merkle_root(&[leaf1.clone(), leaf2.clone()]));
+    assert!(merkle_root(&[leaf1.clone(), leaf2.clone()]) !=
+            merkle_root(&[leaf2.clone(), leaf1.clone()]));
+    assert!(hash_pair(&hash_pair(&leaf1, &leaf2), &hash_pair(&leaf3, &leaf3)) ==
+            merkle_root(&[leaf1, leaf2, leaf3]));
+}
+
+#[test]
+fn test_merkle_root_duplicate_pair() {
+    let leaves: Vec<HashDigest> = range(0, 6).map(
+        |i| hash(format!("leaf{}", i).as_bytes())).collect();
+    assert!(!has_duplicate_pair(&[]));
+    assert!(!has_duplicate_pair(&leaves[..1]));
+    for num_leaves in range(2, 7) {
+        assert!(!has_duplicate_pair(&leaves[..num_leaves]));
+    }
+
+    let mut duplicated = leaves[..3].to_vec();
+    duplicated.push(leaves[2].clone());
+    assert_eq!(merkle_root(&leaves[..3]), merkle_root(&duplicated));
+    assert!(has_duplicate_pair(&duplicated));
+
+    // The same one level up: [0..6] pairs (4, 5) with itself.
+    let mut duplicated = leaves.clone();
+    duplicated.push_all(&leaves[4..]);
+    assert_eq!(merkle_root(&leaves), merkle_root(&duplicated));
+    assert!(has_duplicate_pair(&duplicated));
+}
+
+#[test]
+fn test_merkle_proof() {
+    for num_leaves in range(1, 10) {
+        let leaves: Vec<HashDigest> = range(0, num_leaves).map(
+            |i| hash(format!("leaf{}", i).as_bytes())).collect();
+        let root = merkle_root(&leaves);
+        let middle = num_leaves / 2;
+        for &index in [0, middle, num_leaves - 1].iter() {
+            let proof = merkle_proof(&leaves, index).unwrap();
+            assert!(verify_merkle_proof(&leaves[index], index, num_leaves,
+                                        &proof, &root));
+            assert!(!verify_merkle_proof(&leaves[index], index, num_leaves,
+                                         &proof, &hash(b"other root")));
+            assert!(!verify_merkle_proof(&hash(b"other leaf"), index,
+                                         num_leaves, &proof, &root));
+            if index ^ 1 < num_leaves {
+                assert!(!verify_merkle_proof(
+                    &leaves[index], index ^ 1, num_leaves, &proof, &root));
+            }
+        }
+        assert!(merkle_proof(&leaves, num_leaves).is_err());
+    }
+}
+
+#[test]
+fn test_merkle_proof_single_leaf() {
+    let leaf = hash(b"leaf");
+    let proof = merkle_proof(&[leaf.clone()], 0).unwrap();
+    assert_eq!(0, proof.len());
+    let root = merkle_root(&[leaf.clone()]);
+    assert!(verify_merkle_proof(&leaf, 0, 1, &proof, &root));
+    assert!(!verify_merkle_proof(&leaf, 1, 1, &proof, &root));
+}
+
+#[test]
+fn test_merkle_proof_past_odd_last_leaf() {
+    let leaves: Vec<HashDigest> = range(0, 3).map(
+        |i| hash(format!("leaf{}", i).as_bytes())).collect();
+    let root = merkle_root(&leaves);
+    let proof = merkle_proof(&leaves, 2).unwrap();
+    assert!(verify_merkle_proof(&leaves[2], 2, 3, &proof, &root));
+    // The proof of the duplicated leaf holds at index 3 of a 4 leaf tree...
+    assert!(verify_merkle_proof(&leaves[2], 3, 4, &proof, &root));
+    // ...but there is no leaf 3 in this one.
+    assert!(!verify_merkle_proof(&leaves[2], 3, 3, &proof, &root));
+}
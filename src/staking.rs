@@ -36,11 +36,11 @@ pub struct Staker {
 impl Staker {
     pub fn new(staking_keys: Wallet, head_block: HashDigest,
                head_timestamp: i64) -> Staker {
-        let mut step = HashDigest::from_u64(0);
+        let mut step = HashDigest::zero();
         step.0[step.0.len() - 1] = 3;
         Staker {
             head_block: head_block,
-            target_hash: HashDigest::from_u64(0),
+            target_hash: HashDigest::zero(),
             target_step: step,
             untried_timestamp: head_timestamp + 1,
             staking_keys: staking_keys
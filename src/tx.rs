@@ -2,36 +2,200 @@ use std::collections::HashMap;
 
 use protobuf::Message;
 
-use crypto::{PublicKey, SecretKey, Signature, sign, verify_signature};
-use ironcoin_pb::{Commitment, DetachedSignature, Transaction, Transfer};
-use error::{IroncError, IroncResult};
+use crypto::{HashDigest, PublicKey, SecretKey, Signature, hash, sign,
+             signing_payload, verify_signature};
+use ironcoin_pb::{Commitment, Commitment_Type, DetachedSignature, Transaction,
+                  Transfer};
+use error::{ErrorKind, IroncError, IroncResult};
+
+// A transfer of a transaction with its keys decoded. The source is None for
+// the transfer of a coinbase.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Operation {
+    pub source: Option<PublicKey>,
+    pub destination: PublicKey,
+    pub tokens: u64,
+    pub fee: u64,
+    pub op_index: u32
+}
 
 pub trait TransactionExt {
+    fn canonical_payload(&self) -> Vec<u8>;
+    fn compute_id(&self) -> HashDigest;
+    fn decode_signature_checks(&self)
+                               -> IroncResult<Vec<(PublicKey, Signature)>>;
+    fn has_bounty(&self) -> bool;
+    fn is_bounty_redemption(&self) -> bool;
+    fn is_coinbase(&self) -> bool;
+    fn operations(&self) -> IroncResult<Vec<Operation>>;
+    fn total_fees(&self) -> IroncResult<u64>;
+    fn verify_bounty(&self) -> IroncResult<()>;
+    fn verify_op_sequence(&self) -> IroncResult<()>;
     fn verify_signatures(&self) -> IroncResult<()>;
 }
 
 impl TransactionExt for Transaction {
-    fn verify_signatures(&self) -> IroncResult<()> {
-        let commit_bytes = &try!(self.get_commit().write_to_bytes());
+    // Everything but the signatures, i.e. the commitment which they sign.
+    fn canonical_payload(&self) -> Vec<u8> {
+        signing_payload(self.get_commit())
+    }
+
+    // The id leaves out the signatures, so signing the same transfers again
+    // gives the same id.
+    fn compute_id(&self) -> HashDigest { hash(&self.canonical_payload()) }
+
+    fn decode_signature_checks(&self)
+                               -> IroncResult<Vec<(PublicKey, Signature)>> {
         let mut sign_map = HashMap::<&[u8], &[u8]>::new();
         for sign in self.get_signatures().iter() {
             sign_map.insert(sign.get_public_key(), sign.get_payload());
         }
+        let mut checks = vec![];
         for transfer in self.get_commit().get_transfers().iter() {
             match sign_map.get(transfer.get_source_pk()) {
                 Some(sign_bytes) => {
                     let public_key =
                         try!(PublicKey::from_slice(transfer.get_source_pk()));
                     let signature = try!(Signature::from_slice(sign_bytes));
-                    try!(verify_signature(&public_key, commit_bytes, &signature));
+                    checks.push((public_key, signature));
                 },
                 None => return Err(IroncError::new("Missing key."))
             }
         }
+        Ok(checks)
+    }
+
+    // Whether any part of a bounty is set, which transactions that mint
+    // tokens (in a genesis or a coinbase) must not have.
+    fn has_bounty(&self) -> bool {
+        self.get_commit().get_bounty() != 0 || self.get_commit().has_bounty_pk()
+    }
+
+    // A bounty is paid out of bounty_pk's transfers in the same transaction.
+    fn is_bounty_redemption(&self) -> bool {
+        let commit = self.get_commit();
+        if !commit.has_bounty_pk() || commit.get_bounty() == 0 {
+            return false;
+        }
+        let mut paid = 0u64;
+        for transfer in commit.get_transfers().iter() {
+            if transfer.get_source_pk() == commit.get_bounty_pk() {
+                paid = paid.saturating_add(transfer.get_tokens());
+            }
+        }
+        paid >= commit.get_bounty()
+    }
+
+    // A coinbase has a single transfer without a source or signature, it is
+    // checked as part of its block instead.
+    fn is_coinbase(&self) -> bool {
+        self.get_commit().get_tx_type() == Commitment_Type::COINBASE
+    }
+
+    // The transfers in the order they were added to the TransactionBuilder.
+    fn operations(&self) -> IroncResult<Vec<Operation>> {
+        let mut operations = vec![];
+        for transfer in self.get_commit().get_transfers().iter() {
+            let source = if self.is_coinbase() { None } else {
+                Some(try!(PublicKey::from_slice(transfer.get_source_pk())))
+            };
+            operations.push(Operation {
+                source: source,
+                destination:
+                    try!(PublicKey::from_slice(transfer.get_destination_pk())),
+                tokens: transfer.get_tokens(),
+                fee: transfer.get_fee(),
+                op_index: transfer.get_op_index()
+            });
+        }
+        Ok(operations)
+    }
+
+    fn total_fees(&self) -> IroncResult<u64> {
+        let mut fees = 0u64;
+        for transfer in self.get_commit().get_transfers().iter() {
+            fees = try!(fees.checked_add(transfer.get_fee()).ok_or(
+                IroncError::new("Transaction fees overflow.")));
+        }
+        Ok(fees)
+    }
+
+    // A bounty can only be claimed with a signature from bounty_pk.
+    fn verify_bounty(&self) -> IroncResult<()> {
+        let commit = self.get_commit();
+        if !commit.has_bounty_pk() && commit.get_bounty() == 0 {
+            return Ok(());
+        }
+        if !self.is_bounty_redemption() {
+            return Err(IroncError::new(&format!(
+                "The bounty of {} is not paid out by its bounty_pk.",
+                commit.get_bounty())));
+        }
+        let bounty_pk = try!(PublicKey::from_slice(commit.get_bounty_pk()));
+        let bounty_sign = self.get_signatures().iter().find(
+            |sign| sign.get_public_key() == commit.get_bounty_pk());
+        match bounty_sign {
+            Some(sign) => {
+                let signature = try!(Signature::from_slice(sign.get_payload()));
+                verify_signature(&bounty_pk, &try!(commit.write_to_bytes()),
+                                 &signature)
+            },
+            None => Err(IroncError::with_kind(
+                ErrorKind::SignatureInvalid,
+                &format!("Missing signature for bounty_pk {}.", bounty_pk)))
+        }
+    }
+
+    // The transfers from each source must have consecutive op indices. Where
+    // they start is checked against the ledger when the transaction is applied.
+    fn verify_op_sequence(&self) -> IroncResult<()> {
+        let mut last_op_index = HashMap::<&[u8], u32>::new();
+        for transfer in self.get_commit().get_transfers().iter() {
+            let source_pk = transfer.get_source_pk();
+            let op_index = transfer.get_op_index();
+            let expected = match last_op_index.get(source_pk) {
+                Some(&last) => Some(try!(last.checked_add(1).ok_or(
+                    IroncError::new(&format!(
+                        "Transfer follows one with the last op_index {} for \
+                         its source in this transaction.", last))))),
+                None => None
+            };
+            match expected {
+                Some(expected) if op_index != expected => {
+                    return Err(IroncError::new(&format!(
+                        "Transfer has op_index {} != {} (required) for its \
+                         source in this transaction.", op_index, expected)));
+                },
+                _ => {}
+            }
+            last_op_index.insert(source_pk, op_index);
+        }
+        Ok(())
+    }
+
+    fn verify_signatures(&self) -> IroncResult<()> {
+        let commit_bytes = &try!(self.get_commit().write_to_bytes());
+        for (public_key, signature) in try!(self.decode_signature_checks()) {
+            try!(verify_signature(&public_key, commit_bytes, &signature));
+        }
         Ok(())
     }
 }
 
+// Mints `tokens` to `miner_pk`. The op_index of the transfer is the height of
+// the block, so that the coinbases of different blocks have different ids.
+pub fn make_coinbase(miner_pk: &PublicKey, tokens: u64, height: u32)
+                     -> Transaction {
+    let mut transfer = Transfer::new();
+    transfer.set_op_index(height);
+    transfer.set_tokens(tokens);
+    transfer.mut_destination_pk().push_all(&miner_pk.0);
+    let mut transaction = Transaction::new();
+    transaction.mut_commit().set_tx_type(Commitment_Type::COINBASE);
+    transaction.mut_commit().mut_transfers().push(transfer);
+    transaction
+}
+
 #[derive(Default)]
 pub struct TransactionBuilder {
     transfer_secret_keys: Vec<SecretKey>,
@@ -51,9 +215,16 @@ impl TransactionBuilder {
     pub fn add_transfer(
         &mut self, sk: &SecretKey, source: &PublicKey, destination: &PublicKey,
         tokens: u64, op_index:u32) -> &mut Self {
+        self.add_transfer_with_fee(sk, source, destination, tokens, 0, op_index)
+    }
+
+    pub fn add_transfer_with_fee(
+        &mut self, sk: &SecretKey, source: &PublicKey, destination: &PublicKey,
+        tokens: u64, fee: u64, op_index: u32) -> &mut Self {
         let mut transfer = Transfer::new();
         transfer.set_op_index(op_index);
         transfer.set_tokens(tokens);
+        transfer.set_fee(fee);
         transfer.mut_source_pk().push_all(&source.0);
         transfer.mut_destination_pk().push_all(&destination.0);
 
@@ -62,6 +233,15 @@ impl TransactionBuilder {
         self
     }
 
+    // Pays a bounty of `amount` tokens from `bounty_pk` to `pk`, `sk` being
+    // the secret key of `bounty_pk`.
+    pub fn add_bounty(
+        &mut self, sk: &SecretKey, pk: &PublicKey, bounty_pk: &PublicKey,
+        amount: u64, op_index: u32) -> &mut Self {
+        self.add_transfer(sk, bounty_pk, pk, amount, op_index)
+            .set_bounty(sk, bounty_pk, amount)
+    }
+
     pub fn set_bounty(&mut self, sk: &SecretKey, source: &PublicKey,
                       bounty: u64) -> &mut Self {
         self.bounty_secret_key = Some(sk.clone());
@@ -70,7 +250,45 @@ impl TransactionBuilder {
         self
     }
 
-    pub fn build(self) -> IroncResult<Transaction> {
+    // Drops the transfers and bounty added so far, so the builder can be
+    // reused for the next transaction.
+    pub fn clear(&mut self) -> &mut Self {
+        self.transfer_secret_keys.clear();
+        self.bounty_secret_key = None;
+        self.commit = Commitment::new();
+        self
+    }
+
+    pub fn build(&self) -> IroncResult<Transaction> {
+        let mut transaction = try!(self.sign_commit());
+        transaction.set_commit(self.commit.clone());
+        try!(transaction.verify_signatures());
+        Ok(transaction)
+    }
+
+    // As build, but moves the commitment into the transaction instead of
+    // copying it.
+    pub fn into_transaction(self) -> IroncResult<Transaction> {
+        let mut transaction = try!(self.sign_commit());
+        transaction.set_commit(self.commit);
+        try!(transaction.verify_signatures());
+        Ok(transaction)
+    }
+
+    // A transaction with the signatures but without the commitment itself.
+    fn sign_commit(&self) -> IroncResult<Transaction> {
+        for transfer in self.commit.get_transfers().iter() {
+            if transfer.get_tokens() == 0 {
+                return Err(IroncError::new(&format!(
+                    "Transfer of 0 tokens with op_index {}.",
+                    transfer.get_op_index())));
+            }
+            if transfer.get_tokens().checked_add(transfer.get_fee()).is_none() {
+                return Err(IroncError::new(&format!(
+                    "Transfer of {} tokens with a fee of {} overflows.",
+                    transfer.get_tokens(), transfer.get_fee())));
+            }
+        }
         let mut transaction = Transaction::new();
         let commit_bytes = &self.commit.write_to_bytes().unwrap();
         for (transfer, secret_key) in self.commit.get_transfers().iter()
@@ -89,8 +307,294 @@ impl TransactionBuilder {
                     IroncError::new("Invalid key for source account."))
             }
         }
-        transaction.set_commit(self.commit);
-        try!(transaction.verify_signatures());
+        if let Some(ref bounty_sk) = self.bounty_secret_key {
+            let bounty_pk =
+                try!(PublicKey::from_slice(self.commit.get_bounty_pk()));
+            let already_signed = transaction.get_signatures().iter().any(
+                |sign| sign.get_public_key() == bounty_pk.as_slice());
+            if !already_signed {
+                let signature = sign(bounty_sk, commit_bytes);
+                if verify_signature(&bounty_pk, commit_bytes,
+                                    &signature).is_err() {
+                    return Err(
+                        IroncError::new("Invalid key for bounty account."));
+                }
+                let mut sign = DetachedSignature::new();
+                sign.set_public_key(bounty_pk.0.to_vec());
+                sign.set_payload(signature.0.to_vec());
+                transaction.mut_signatures().push(sign);
+            }
+        }
         Ok(transaction)
     }
 }
+
+/*****  Tests  *****/
+
+use std::{u32, u64};
+
+use crypto::gen_keypair;
+
+#[test]
+fn test_transaction_builder_fees() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 100, 3, 0)
+        .add_transfer_with_fee(&sk2, &pk2, &pk1, 10, 2, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 1);
+    let transaction = tx_builder.build().unwrap();
+    assert!(transaction.verify_signatures().is_ok());
+    assert_eq!(5, transaction.total_fees().unwrap());
+    assert_eq!(3, transaction.get_commit().get_transfers()[0].get_fee());
+    assert_eq!(0, transaction.get_commit().get_transfers()[2].get_fee());
+}
+
+#[test]
+fn test_transaction_builder_fee_overflow() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, u64::MAX - 1, 1, 0);
+    assert!(tx_builder.build().is_ok());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, u64::MAX, 1, 0);
+    assert!(tx_builder.build().is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 1, u64::MAX - 1, 0)
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 1, 1, 1);
+    assert_eq!(u64::MAX, tx_builder.build().unwrap().total_fees().unwrap());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 1, u64::MAX - 1, 0)
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 1, 2, 1);
+    assert!(tx_builder.build().unwrap().total_fees().is_err());
+}
+
+#[test]
+fn test_transaction_compute_id() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0);
+    let transaction = tx_builder.build().unwrap();
+    assert_eq!(transaction.compute_id(), transaction.clone().compute_id());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 100, 0);
+    let same_content = tx_builder.build().unwrap();
+    assert_eq!(transaction.compute_id(), same_content.compute_id());
+
+    let mut changed = transaction.clone();
+    changed.mut_commit().mut_transfers()[0].set_tokens(101);
+    assert!(transaction.compute_id() != changed.compute_id());
+
+    let mut changed = transaction.clone();
+    changed.mut_signatures()[0].mut_payload()[0] ^= 1;
+    assert_eq!(transaction.compute_id(), changed.compute_id());
+}
+
+#[test]
+fn test_transaction_id_ignores_signatures() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 100, 0)
+        .add_transfer(&sk2, &pk2, &pk1, 5, 0);
+    let transaction = tx_builder.build().unwrap();
+    assert_eq!(transaction.get_commit().write_to_bytes().unwrap(),
+               transaction.canonical_payload());
+
+    let mut resigned = transaction.clone();
+    resigned.clear_signatures();
+    assert_eq!(transaction.compute_id(), resigned.compute_id());
+    let commit_bytes = resigned.canonical_payload();
+    for (sk, pk) in [(&sk2, &pk2), (&sk1, &pk1)].iter() {
+        let mut sign_pb = DetachedSignature::new();
+        sign_pb.set_public_key(pk.0.to_vec());
+        sign_pb.set_payload(sign(*sk, &commit_bytes).0.to_vec());
+        resigned.mut_signatures().push(sign_pb);
+    }
+    assert!(resigned.verify_signatures().is_ok());
+    assert!(resigned != transaction);
+    assert_eq!(transaction.compute_id(), resigned.compute_id());
+}
+
+#[test]
+fn test_transaction_verify_op_sequence() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, 3)
+        .add_transfer(&sk2, &pk2, &pk1, 1, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 4)
+        .add_transfer(&sk2, &pk2, &pk1, 1, 1);
+    assert!(tx_builder.build().unwrap().verify_op_sequence().is_ok());
+    assert!(Transaction::new().verify_op_sequence().is_ok());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 2);
+    assert!(tx_builder.build().unwrap().verify_op_sequence().is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0)
+        .add_transfer(&sk2, &pk2, &pk1, 1, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0);
+    assert!(tx_builder.build().unwrap().verify_op_sequence().is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, 1)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0);
+    assert!(tx_builder.build().unwrap().verify_op_sequence().is_err());
+
+    // Nothing can follow the last op_index.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, u32::MAX)
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0);
+    assert!(tx_builder.build().unwrap().verify_op_sequence().is_err());
+}
+
+#[test]
+fn test_transaction_builder_add_bounty() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_bounty(&sk1, &pk2, &pk1, 50, 0);
+    let transaction = tx_builder.build().unwrap();
+    assert!(transaction.verify_signatures().is_ok());
+    assert!(transaction.verify_bounty().is_ok());
+    assert!(transaction.is_bounty_redemption());
+    assert_eq!(pk1.as_slice(), transaction.get_commit().get_bounty_pk());
+    assert_eq!(50, transaction.get_commit().get_bounty());
+    let transfer = &transaction.get_commit().get_transfers()[0];
+    assert_eq!(pk1.as_slice(), transfer.get_source_pk());
+    assert_eq!(pk2.as_slice(), transfer.get_destination_pk());
+    assert_eq!(1, transaction.get_signatures().len());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let plain = tx_builder.build().unwrap();
+    assert!(!plain.is_bounty_redemption());
+    assert!(plain.verify_bounty().is_ok());
+
+    // The bounty is larger than what bounty_pk pays out.
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0).set_bounty(&sk2, &pk2, 5);
+    let unpaid = tx_builder.build().unwrap();
+    assert_eq!(2, unpaid.get_signatures().len());
+    assert!(!unpaid.is_bounty_redemption());
+    assert!(unpaid.verify_bounty().is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_bounty(&sk2, &pk2, &pk1, 50, 0);
+    assert!(tx_builder.build().is_err());
+
+    let mut unsigned = transaction.clone();
+    unsigned.clear_signatures();
+    assert_eq!(ErrorKind::SignatureInvalid,
+               unsigned.verify_bounty().unwrap_err().kind());
+}
+
+#[test]
+fn test_transaction_builder_zero_tokens() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 0, 0);
+    assert!(tx_builder.build().is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer(&sk1, &pk1, &pk2, 1, 0)
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 0, 5, 1);
+    assert!(tx_builder.build().is_err());
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 1, 0);
+    assert!(tx_builder.build().is_ok());
+}
+
+#[test]
+fn test_transaction_builder_clear() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_bounty(&sk1, &pk2, &pk1, 50, 0)
+        .add_transfer(&sk1, &pk1, &pk2, 10, 1);
+    let first = tx_builder.build().unwrap();
+    assert_eq!(2, first.get_commit().get_transfers().len());
+
+    tx_builder.clear().add_transfer(&sk2, &pk2, &pk1, 7, 0);
+    let second = tx_builder.build().unwrap();
+    assert!(second.verify_signatures().is_ok());
+    assert_eq!(1, second.get_signatures().len());
+    assert!(!second.get_commit().has_bounty_pk());
+    assert_eq!(0, second.get_commit().get_bounty());
+    let transfers = second.get_commit().get_transfers();
+    assert_eq!(1, transfers.len());
+    assert_eq!(pk2.as_slice(), transfers[0].get_source_pk());
+    assert_eq!(7, transfers[0].get_tokens());
+
+    // Building twice from the same operations gives the same transaction.
+    assert_eq!(second.compute_id(), tx_builder.build().unwrap().compute_id());
+
+    tx_builder.clear();
+    assert_eq!(0, tx_builder.build().unwrap().get_commit().get_transfers().len());
+}
+
+#[test]
+fn test_transaction_operations() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder
+        .add_transfer_with_fee(&sk1, &pk1, &pk2, 100, 2, 7)
+        .add_transfer(&sk2, &pk2, &pk1, 5, 0);
+    let transaction = tx_builder.build().unwrap();
+    assert_eq!(vec![
+        Operation { source: Some(pk1.clone()), destination: pk2.clone(),
+                    tokens: 100, fee: 2, op_index: 7 },
+        Operation { source: Some(pk2.clone()), destination: pk1.clone(),
+                    tokens: 5, fee: 0, op_index: 0 }],
+               transaction.operations().unwrap());
+
+    let coinbase = make_coinbase(&pk1, 50, 3);
+    assert_eq!(vec![Operation { source: None, destination: pk1.clone(),
+                                tokens: 50, fee: 0, op_index: 3 }],
+               coinbase.operations().unwrap());
+
+    let mut bad_key = transaction.clone();
+    bad_key.mut_commit().mut_transfers()[1].set_destination_pk(vec![1, 2]);
+    assert!(bad_key.operations().is_err());
+}
+
+#[test]
+fn test_transaction_has_bounty() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, _) = gen_keypair();
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer(&sk1, &pk1, &pk2, 10, 0);
+    let mut transaction = tx_builder.build().unwrap();
+    assert!(!transaction.has_bounty());
+    assert!(!make_coinbase(&pk1, 50, 1).has_bounty());
+
+    // Either half of a bounty counts.
+    transaction.mut_commit().set_bounty(5);
+    assert!(transaction.has_bounty());
+    transaction.mut_commit().set_bounty(0);
+    assert!(!transaction.has_bounty());
+    transaction.mut_commit().set_bounty_pk(pk2.0.to_vec());
+    assert!(transaction.has_bounty());
+}
@@ -7,9 +7,11 @@ use protobuf::{self, MessageStatic, RepeatedField};
 use rustc_serialize::base64::{self, ToBase64};
 use sodiumoxide::crypto::sign::ed25519;
 
+use chain::Blockchain;
 use crypto::{PublicKey, SecretKey};
-use error::IroncResult;
-use ironcoin_pb::{Wallet, WalletKeypair};
+use error::{IroncError, IroncResult};
+use ironcoin_pb::{Transaction, Wallet, WalletKeypair};
+use tx::TransactionBuilder;
 
 pub fn load_proto_from_file<Message: MessageStatic>(
     path: &str) -> IroncResult<Message>
@@ -138,8 +140,43 @@ impl WalletKeypairExt for WalletKeypair {
     }
 }
 
+// An account tracked through a chain's ledger, as opposed to the `Wallet`
+// proto which only stores keys.
+pub struct Account {
+    public_key: PublicKey
+}
+
+impl Account {
+    pub fn new(public_key: PublicKey) -> Account {
+        Account { public_key: public_key }
+    }
+
+    pub fn public_key(&self) -> &PublicKey { &self.public_key }
+
+    pub fn balance(&self, chain: &Blockchain) -> u64 {
+        chain.balance_of(&self.public_key)
+    }
+
+    pub fn create_transfer(&self, chain: &Blockchain, secret_key: &SecretKey,
+                           destination: &PublicKey, tokens: u64)
+                           -> IroncResult<Transaction> {
+        let balance = self.balance(chain);
+        if tokens > balance {
+            return Err(IroncError::new(&format!(
+                "Not enough funds. Balance of {} is {} but {} tokens were \
+                 requested.", self.public_key, balance, tokens)));
+        }
+        let mut tx_builder = TransactionBuilder::new();
+        tx_builder.add_transfer(
+            secret_key, &self.public_key, destination, tokens,
+            chain.next_op_index(&self.public_key));
+        tx_builder.build()
+    }
+}
+
 /*****  Tests  *****/
 
+use block::{BlockBuilder, GenesisBuilder};
 use crypto::gen_keypair;
 
 #[test]
@@ -161,3 +198,78 @@ fn test_wallet_ext_add_key() {
 fn test_wallet_ext_add_public_key() {
 
 }
+
+#[test]
+fn test_account_balance_and_create_transfer() {
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let mut genesis_builder = GenesisBuilder::new();
+    genesis_builder.add_transfer(pk1.clone(), 1000);
+    let genesis = genesis_builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis).unwrap();
+
+    let account1 = Account::new(pk1.clone());
+    let account2 = Account::new(pk2.clone());
+    assert_eq!(1000, account1.balance(&chain));
+    assert_eq!(0, account2.balance(&chain));
+
+    assert!(account1.create_transfer(&chain, &sk1, &pk2, 1001).is_err());
+    assert!(account2.create_transfer(&chain, &sk2, &pk1, 1).is_err());
+
+    let transaction = account1.create_transfer(&chain, &sk1, &pk2, 300).unwrap();
+    let mut block_builder = BlockBuilder::new(chain.tip().clone(), 1);
+    block_builder.add_transaction(transaction);
+    chain.append(block_builder.build(&sk2).unwrap()).unwrap();
+    assert_eq!(700, account1.balance(&chain));
+    assert_eq!(300, account2.balance(&chain));
+
+    assert!(account1.create_transfer(&chain, &sk1, &pk2, 701).is_err());
+    let transaction = account1.create_transfer(&chain, &sk1, &pk2, 700).unwrap();
+    assert_eq!(1, transaction.get_commit().get_transfers()[0].get_op_index());
+    let mut block_builder = BlockBuilder::new(chain.tip().clone(), 2);
+    block_builder.add_transaction(transaction);
+    chain.append(block_builder.build(&sk2).unwrap()).unwrap();
+    assert_eq!(0, account1.balance(&chain));
+    assert_eq!(1000, account2.balance(&chain));
+
+    // Pruned transfers still count, as does their op_index.
+    chain.prune(3);
+    assert_eq!(1000, account2.balance(&chain));
+    let transaction = account2.create_transfer(&chain, &sk2, &pk1, 10).unwrap();
+    assert_eq!(0, transaction.get_commit().get_transfers()[0].get_op_index());
+    let mut block_builder = BlockBuilder::new(chain.tip().clone(), 3);
+    block_builder.add_transaction(transaction);
+    chain.append(block_builder.build(&sk2).unwrap()).unwrap();
+    assert_eq!(10, account1.balance(&chain));
+    assert_eq!(990, account2.balance(&chain));
+    let transaction = account2.create_transfer(&chain, &sk2, &pk1, 10).unwrap();
+    assert_eq!(1, transaction.get_commit().get_transfers()[0].get_op_index());
+}
+
+#[test]
+fn test_account_balance_with_coinbase() {
+    use block::BLOCK_REWARD;
+
+    let (pk1, sk1) = gen_keypair();
+    let (pk2, sk2) = gen_keypair();
+    let (miner_pk, _) = gen_keypair();
+    let mut genesis_builder = GenesisBuilder::new();
+    genesis_builder.add_transfer(pk1.clone(), 1000);
+    let genesis = genesis_builder.build().unwrap();
+    let mut chain = Blockchain::new(genesis).unwrap();
+
+    let mut tx_builder = TransactionBuilder::new();
+    tx_builder.add_transfer_with_fee(&sk1, &pk1, &pk2, 100, 5, 0);
+    let mut block_builder = BlockBuilder::new(chain.tip().clone(), 1);
+    block_builder
+        .add_transaction(tx_builder.build().unwrap())
+        .set_coinbase(miner_pk.clone());
+    chain.append(block_builder.build(&sk2).unwrap()).unwrap();
+
+    // The fees go to the miner through the coinbase, not to the staker.
+    assert_eq!(895, Account::new(pk1.clone()).balance(&chain));
+    assert_eq!(100, Account::new(pk2.clone()).balance(&chain));
+    let miner = Account::new(miner_pk.clone());
+    assert_eq!(BLOCK_REWARD + 5, miner.balance(&chain));
+    assert_eq!(0, chain.next_op_index(&miner_pk));
+}